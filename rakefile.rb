@@ -47,3 +47,34 @@ task :perf do
             end
     end
 end
+
+desc "Compare reading and memory-mapping files of different sizes, best of 11 runs"
+task :perf_mmap do
+    dir = "/tmp/mo-perf-mmap"
+    total_size = 256*1024*1024
+    run_count = 11
+    [4, 64, 256, 1024, 4096, 16384].each do |kb|
+        subdir = File.join(dir, kb.to_s)
+        unless File.exist?(subdir)
+            FileUtils.mkdir_p(subdir)
+            line = "#{"a"*99}\n"
+            content = line*(kb*1024/line.size)
+            [total_size/(kb*1024), 2000].min.times { |ix| File.write(File.join(subdir, "f#{ix}.txt"), content) }
+        end
+        puts("\nFiles of #{kb}KB")
+        best = {}
+        %w[never always].each do |mode|
+            cmd = "mo -i -C #{subdir} -l --mmap #{mode} @@@"
+            puts("  Running `#{cmd}` #{run_count} times")
+            #The first run warms the cache and is not measured
+            system(cmd) or fail("Could not run `#{cmd}`")
+            best[mode] = run_count.times.map do
+                start = Process.clock_gettime(Process::CLOCK_MONOTONIC)
+                system(cmd)
+                Process.clock_gettime(Process::CLOCK_MONOTONIC)-start
+            end.min
+            puts("    Best time: #{(best[mode]*1000).round(1)}ms")
+        end
+        puts("  Speedup of mapping: #{(best["never"]/best["always"]).round(2)}x")
+    end
+end
@@ -0,0 +1,207 @@
+use crate::util::{MyError, Result};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+//Stream compression formats that can be decompressed on the fly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Compressed(Compression),
+    Zip,
+    Tar(Option<Compression>),
+}
+
+impl Kind {
+    //Detects the kind of compression or archive from the extension(s) of `path`
+    pub fn from_path(path: &Path) -> Option<Kind> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+
+        let compressions = [
+            (".gz", Compression::Gzip),
+            (".gzip", Compression::Gzip),
+            (".xz", Compression::Xz),
+            (".zst", Compression::Zstd),
+            (".zstd", Compression::Zstd),
+        ];
+        for (extension, compression) in compressions.iter() {
+            if let Some(stem) = name.strip_suffix(extension) {
+                if stem.ends_with(".tar") {
+                    return Some(Kind::Tar(Some(*compression)));
+                }
+                return Some(Kind::Compressed(*compression));
+            }
+        }
+
+        if name.ends_with(".tgz") {
+            Some(Kind::Tar(Some(Compression::Gzip)))
+        } else if name.ends_with(".txz") {
+            Some(Kind::Tar(Some(Compression::Xz)))
+        } else if name.ends_with(".tzst") {
+            Some(Kind::Tar(Some(Compression::Zstd)))
+        } else if name.ends_with(".tar") {
+            Some(Kind::Tar(None))
+        } else if name.ends_with(".zip") {
+            Some(Kind::Zip)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_archive(&self) -> bool {
+        !matches!(self, Kind::Compressed(_))
+    }
+}
+
+//Path used to report a member of an archive, eg, `bundle.zip:dir/file.txt`
+pub fn member_path(archive: &Path, member: &Path) -> PathBuf {
+    let mut path = OsString::from(archive.as_os_str());
+    path.push(":");
+    path.push(member.as_os_str());
+    PathBuf::from(path)
+}
+
+//Appends the decompressed content of the file at `path` to `content`
+pub fn decompress(path: &Path, compression: Compression, content: &mut Vec<u8>) -> Result<()> {
+    let file = File::open(path)?;
+    decoder_(compression, BufReader::new(file))?.read_to_end(content)?;
+    Ok(())
+}
+
+//Calls `ftor` with the name and content of each regular file in the archive at `path`
+pub fn for_each_member<F>(path: &Path, kind: Kind, mut ftor: F) -> Result<()>
+where
+    F: FnMut(&Path, &[u8]) -> Result<()>,
+{
+    let mut content = vec![];
+    match kind {
+        Kind::Compressed(_) => fail!("'{}' is not an archive", path.display()),
+
+        Kind::Zip => {
+            let mut zip = zip::ZipArchive::new(File::open(path)?)?;
+            for ix in 0..zip.len() {
+                let mut member = zip.by_index(ix)?;
+                if !member.is_file() {
+                    continue;
+                }
+                //Members with names that escape the archive root are skipped
+                let name = match member.enclosed_name() {
+                    None => continue,
+                    Some(name) => name.to_path_buf(),
+                };
+                content.clear();
+                member.read_to_end(&mut content)?;
+                ftor(&name, &content)?;
+            }
+        }
+
+        Kind::Tar(compression_opt) => {
+            let reader = BufReader::new(File::open(path)?);
+            let reader: Box<dyn Read> = match compression_opt {
+                None => Box::new(reader),
+                Some(compression) => decoder_(compression, reader)?,
+            };
+            let mut tar = tar::Archive::new(reader);
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.to_path_buf();
+                content.clear();
+                entry.read_to_end(&mut content)?;
+                ftor(&name, &content)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decoder_<'a, R>(compression: Compression, reader: R) -> Result<Box<dyn Read + 'a>>
+where
+    R: Read + 'a,
+{
+    let decoder: Box<dyn Read + 'a> = match compression {
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(ruzstd::StreamingDecoder::new(reader)?),
+        Compression::Xz => {
+            //lzma-rs has no streaming reader, we decompress in memory
+            let mut buffer = vec![];
+            lzma_rs::xz_decompress(&mut BufReader::new(reader), &mut buffer)?;
+            Box::new(Cursor::new(buffer))
+        }
+    };
+    Ok(decoder)
+}
+
+#[test]
+fn test_kind_from_path() {
+    let scns = [
+        ("a.txt", None),
+        ("a.log.gz", Some(Kind::Compressed(Compression::Gzip))),
+        ("a.XZ", Some(Kind::Compressed(Compression::Xz))),
+        ("a.zst", Some(Kind::Compressed(Compression::Zstd))),
+        ("a.tar", Some(Kind::Tar(None))),
+        ("a.tar.gz", Some(Kind::Tar(Some(Compression::Gzip)))),
+        ("a.tgz", Some(Kind::Tar(Some(Compression::Gzip)))),
+        ("a.tar.zst", Some(Kind::Tar(Some(Compression::Zstd)))),
+        ("dir/a.zip", Some(Kind::Zip)),
+    ];
+    for (path, kind) in scns.iter() {
+        assert_eq!(Kind::from_path(Path::new(path)), *kind, "{}", path);
+    }
+}
+
+#[test]
+fn test_archive_members() -> Result<()> {
+    use std::io::Write;
+
+    let tmp = crate::util::TempDir::new("archive")?;
+    let root = &tmp.path;
+
+    let gz_path = root.join("a.txt.gz");
+    let mut encoder =
+        flate2::write::GzEncoder::new(File::create(&gz_path)?, flate2::Compression::default());
+    encoder.write_all(b"needle\n")?;
+    encoder.finish()?;
+    let mut content = vec![];
+    decompress(&gz_path, Compression::Gzip, &mut content)?;
+    assert_eq!(content, b"needle\n");
+
+    let zip_path = root.join("bundle.zip");
+    let mut zip = zip::ZipWriter::new(File::create(&zip_path)?);
+    zip.add_directory("dir/", zip::write::FileOptions::default())?;
+    zip.start_file("dir/a.txt", zip::write::FileOptions::default())?;
+    zip.write_all(b"needle\n")?;
+    zip.finish()?;
+
+    let tgz_path = root.join("bundle.tar.gz");
+    let encoder =
+        flate2::write::GzEncoder::new(File::create(&tgz_path)?, flate2::Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(7);
+    header.set_cksum();
+    tar.append_data(&mut header, "dir/b.txt", &b"needle\n"[..])?;
+    tar.into_inner()?.finish()?;
+
+    for (path, member) in [(&zip_path, "dir/a.txt"), (&tgz_path, "dir/b.txt")].iter() {
+        let mut members = vec![];
+        for_each_member(path, Kind::from_path(path).unwrap(), |name, content| {
+            members.push((member_path(path, name), content.to_vec()));
+            Ok(())
+        })?;
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].0, member_path(path, Path::new(member)));
+        assert_eq!(members[0].1, b"needle\n");
+    }
+
+    Ok(())
+}
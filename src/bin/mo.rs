@@ -1,6 +1,9 @@
 extern crate molybdenum;
 use atty::Stream;
 use molybdenum::cli;
+use molybdenum::config;
+use molybdenum::editor;
+use molybdenum::encoding;
 use molybdenum::file;
 use molybdenum::search;
 use molybdenum::util;
@@ -10,8 +13,28 @@ use std::process::Command;
 fn main() -> util::Result<()> {
     let mut options = cli::Options::new();
 
-    options.parse(cli::args())?;
+    //Config arguments are parsed first, the CLI arguments can override them
+    let mut args = cli::args();
+    //`--no-OPTION` turns off an option from the config
+    let negated = cli::take_negated(&mut args);
+    let mut config_args = cli::Args::new();
+    let mut config_paths = vec![];
+    if cli::peek(&args, "--no-config").is_empty() {
+        let config = config::Config::load(&cli::peek(&args, "--root"))?;
+        let profile_opt = cli::peek(&args, "--profile").pop();
+        config_args = config.apply(profile_opt.as_deref(), &mut options)?;
+        config_paths = config.paths;
+    }
+    for lh in negated {
+        config::turn_off(lh, &mut config_args, &mut options)?;
+    }
+    config_args.append(&mut args);
+
+    options.parse(config_args)?;
     if options.verbose_level >= 1 {
+        for path in config_paths.iter() {
+            println!("Using config '{}'", path.display());
+        }
         println!("{:?}", options);
     }
 
@@ -24,6 +47,17 @@ fn main() -> util::Result<()> {
         return Ok(());
     }
 
+    if !options.why_ignored_vec.is_empty() {
+        for path in &options.why_ignored_vec {
+            molybdenum::process_why_ignored(path, &options)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(command) = options.index_command_opt {
+        return molybdenum::process_index(command, &options);
+    }
+
     let stdin_is_console = atty::is(Stream::Stdin);
     if options.verbose_level >= 1 {
         println!(
@@ -36,7 +70,9 @@ fn main() -> util::Result<()> {
         );
     }
 
-    let input_from_file = options.input_from_file_opt.unwrap_or(stdin_is_console);
+    //When filepaths are provided via --files-from, Stdin cannot be used for content
+    let input_from_file =
+        options.files_from_opt.is_some() || options.input_from_file_opt.unwrap_or(stdin_is_console);
     if input_from_file {
         if options.verbose_level >= 1 {
             println!("Taking input from file");
@@ -58,26 +94,59 @@ fn main() -> util::Result<()> {
             ));
         }
         let mut file_data = file::Data::new(search_opt, options.invert_pattern, replace_opt);
+        if let Some(search) = &file_data.search_opt {
+            file_data.extract_opt = molybdenum::create_extract(&options, search)?;
+            file_data.template_opt = molybdenum::create_template(&options, search)?;
+        }
+        file_data.theme = molybdenum::create_theme(&options)?;
+        file_data.hyperlink_opt = molybdenum::create_hyperlink(&options)?;
+        file_data.decompress = options.search_compressed;
+        file_data.line_ending = options.line_ending;
+        file_data.separator = options.separator;
+        file_data.context_before = options.output_before;
+        file_data.context_after = options.output_after;
+        //Replaced content is written back, it should not be backed by the file itself
+        if options.replace_opt.is_none() || options.simulate_replace {
+            file_data.mmap_mode = options.mmap_mode;
+        }
+        if let Some(label) = &options.encoding_opt {
+            file_data.encoding_opt = Some(encoding::from_label(label)?);
+        }
 
-        if options.roots.is_empty() {
-            molybdenum::process_folder(".", &options, &mut file_data)?;
+        if options.watch {
+            molybdenum::watch::watch(&options, &mut file_data)?;
+        } else if options.tui {
+            molybdenum::tui::run(&options, &mut file_data)?;
+        } else if options.pick {
+            molybdenum::picker::pick(&options, &mut file_data)?;
         } else {
-            for root in &options.roots {
-                let root = std::path::PathBuf::from(root);
-                if root.is_dir() {
-                    molybdenum::process_folder(&root, &options, &mut file_data)?;
-                } else {
-                    molybdenum::process_file(&root, &options, &mut file_data)?;
-                }
-            }
+            molybdenum::process_paths(&options, &mut file_data)?;
         }
 
-        if options.open {
+        //Nothing is opened when picking was cancelled
+        if options.open && !(options.pick && file_data.filepaths.is_empty()) {
             let editor = env::var("EDITOR").unwrap_or("hx".to_string());
-            let mut cmd = Command::new(editor);
-            for fp in file_data.filepaths {
-                cmd.arg(fp);
-            }
+            let files = editor::files(&file_data.filepaths, &file_data.locations);
+            let template = match &options.open_with_opt {
+                Some(template) => template.clone(),
+                None => env::var(editor::OPEN_ENV).unwrap_or_else(|_| {
+                    editor::Command::default_template(&editor, files.len()).to_string()
+                }),
+            };
+            let command = editor::Command::new(&template)?;
+            //The quickfix file is removed when the editor exits
+            let quickfix_opt = if command.needs_quickfix() {
+                Some(editor::Quickfix::create(&files, &file_data.locations)?)
+            } else {
+                None
+            };
+            let quickfix = quickfix_opt
+                .as_ref()
+                .map(|quickfix| quickfix.path.clone())
+                .unwrap_or_default();
+            let args = command.expand(&editor, &files, &quickfix);
+            let mut cmd = Command::new(&args[0]);
+            cmd.args(&args[1..]);
             cmd.status().expect("Could not run editor");
         }
     } else {
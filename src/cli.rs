@@ -5,15 +5,59 @@ use std::ffi::OsString;
 
 //<Specific part of CLI handling>
 //
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum OutputOnly {
     Filenames,
     Folders,
     Match,
 }
 //
+//Line endings to use when writing a file with replacements
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LineEnding {
+    Preserve,
+    Lf,
+    CrLf,
+}
+//
+//Separates the records that are matched individually, by default, these are lines
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Separator {
+    Byte(u8),
+    //Records are separated by one or more blank lines
+    Paragraph,
+}
+//
+//Order in which files and folders are output, ties are ordered by path
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SortKey {
+    Path,
+    Mtime,
+    Matches,
+    Size,
+}
+//
+//Maintenance of the trigram index that narrows down the files to search
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IndexCommand {
+    Build,
+    //Only files with a different mtime or size are read again
+    Update,
+    Info,
+    Drop,
+}
+//
+//When to memory-map files instead of reading them into a buffer
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MmapMode {
+    Never,
+    //Only files that are large enough to benefit from it
+    Auto,
+    Always,
+}
+//
 //Represents parsed CLI options
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Options {
     pub output_help: bool,
     pub roots: Vec<String>,
@@ -31,6 +75,8 @@ pub struct Options {
     pub case_sensitive: bool,
     pub extensions: Vec<OsString>,
     pub extension_sets: Vec<OsString>,
+    //Extension sets from the config files, next to the built-in sets
+    pub custom_extension_sets: Vec<(String, Vec<String>)>,
     pub search_binary_files: bool,
     pub file_include_pattern_vec: Vec<String>,
     pub file_exclude_pattern_vec: Vec<String>,
@@ -40,6 +86,39 @@ pub struct Options {
     pub console_output: std::option::Option<bool>,
     pub color_output: std::option::Option<bool>,
     pub open: bool,
+    pub open_with_opt: std::option::Option<String>,
+    pub pick: bool,
+    pub tui: bool,
+    pub watch: bool,
+    //In watch mode, only output the matches that appeared or disappeared
+    pub watch_diff: bool,
+    pub ignore_file_vec: Vec<String>,
+    pub why_ignored_vec: Vec<String>,
+    pub index_command_opt: std::option::Option<IndexCommand>,
+    pub profile_opt: std::option::Option<String>,
+    pub skip_config: bool,
+    pub follow_links: bool,
+    pub same_file_system: bool,
+    pub files_from_opt: std::option::Option<String>,
+    pub search_compressed: bool,
+    pub encoding_opt: std::option::Option<String>,
+    pub line_ending: LineEnding,
+    pub separator: Separator,
+    pub stream_threshold: u64,
+    pub mmap_mode: MmapMode,
+    pub passthrough: bool,
+    pub group_vec: Vec<String>,
+    pub group_separator: String,
+    pub group_template_opt: std::option::Option<String>,
+    pub template_opt: std::option::Option<String>,
+    pub line_template_opt: std::option::Option<String>,
+    pub theme_opt: std::option::Option<String>,
+    pub colors_vec: Vec<String>,
+    pub hyperlink_opt: std::option::Option<String>,
+    pub max_columns: usize,
+    pub match_window_opt: std::option::Option<usize>,
+    pub sort_opt: std::option::Option<SortKey>,
+    pub sort_descending: bool,
 }
 
 //Default values for Options
@@ -62,6 +141,7 @@ impl Default for Options {
             case_sensitive: false,
             extensions: vec![],
             extension_sets: vec![],
+            custom_extension_sets: vec![],
             search_binary_files: false,
             file_include_pattern_vec: vec![],
             file_exclude_pattern_vec: vec![],
@@ -71,17 +151,99 @@ impl Default for Options {
             console_output: None,
             color_output: None,
             open: false,
+            open_with_opt: None,
+            pick: false,
+            tui: false,
+            watch: false,
+            watch_diff: false,
+            ignore_file_vec: vec![],
+            why_ignored_vec: vec![],
+            index_command_opt: None,
+            profile_opt: None,
+            skip_config: false,
+            follow_links: false,
+            same_file_system: false,
+            files_from_opt: None,
+            search_compressed: false,
+            encoding_opt: None,
+            line_ending: LineEnding::Preserve,
+            separator: Separator::Byte(0x0a_u8),
+            stream_threshold: 64 * 1024 * 1024,
+            mmap_mode: MmapMode::Never,
+            passthrough: false,
+            group_vec: vec![],
+            group_separator: "\t".to_string(),
+            group_template_opt: None,
+            template_opt: None,
+            line_template_opt: None,
+            theme_opt: None,
+            colors_vec: vec![],
+            hyperlink_opt: None,
+            max_columns: 0,
+            match_window_opt: None,
+            sort_opt: None,
+            sort_descending: false,
         }
     }
 }
 
 fn parse_boolean(s: &str) -> bool {
-    match s {
-        "true" | "1" | "y" | "Y" | "yes" | "Yes" | "YES" => true,
-        _ => false,
+    matches!(s, "true" | "1" | "y" | "Y" | "yes" | "Yes" | "YES")
+}
+
+fn parse_separator(s: &str) -> Result<Separator> {
+    let separator = match s {
+        "newline" | "lf" => Separator::Byte(0x0a_u8),
+        "null" | "nul" => Separator::Byte(0x00_u8),
+        "paragraph" => Separator::Paragraph,
+        _ if s.len() == 1 => Separator::Byte(s.as_bytes()[0]),
+        _ => match s.strip_prefix("0x").map(|hex| u8::from_str_radix(hex, 16)) {
+            Some(Ok(byte)) => Separator::Byte(byte),
+            _ => fail!("Unknown separator '{}'", s),
+        },
+    };
+    Ok(separator)
+}
+
+//Parses a number of bytes with an optional K, M or G suffix
+fn parse_size(s: &str) -> Result<u64> {
+    let (number, factor) = match s.char_indices().last() {
+        Some((ix, 'K')) | Some((ix, 'k')) => (&s[..ix], 1024),
+        Some((ix, 'M')) | Some((ix, 'm')) => (&s[..ix], 1024 * 1024),
+        Some((ix, 'G')) | Some((ix, 'g')) => (&s[..ix], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    match number.parse::<u64>() {
+        Err(_) => fail!("Could not convert '{}' into a size", s),
+        Ok(v) => match v.checked_mul(factor) {
+            None => fail!("Size '{}' is too large", s),
+            Some(size) => Ok(size),
+        },
     }
 }
 
+fn parse_index_command(s: &str) -> Result<IndexCommand> {
+    let command = match s {
+        "build" => IndexCommand::Build,
+        "update" => IndexCommand::Update,
+        "info" => IndexCommand::Info,
+        "drop" => IndexCommand::Drop,
+        _ => fail!("Unknown index command '{}', use build, update, info or drop", s),
+    };
+    Ok(command)
+}
+
+fn parse_sort_key(s: &str) -> Result<SortKey> {
+    let sort_key = match s {
+        "path" => SortKey::Path,
+        "mtime" => SortKey::Mtime,
+        "matches" => SortKey::Matches,
+        "size" => SortKey::Size,
+        _ => fail!("Unknown sort key '{}', use path, mtime, matches or size", s),
+    };
+    Ok(sort_key)
+}
+
 //Creates a Vec of CLI option handlers
 fn generate_option_vec() -> Vec<Option> {
     vec![
@@ -108,6 +270,16 @@ fn generate_option_vec() -> Vec<Option> {
             options.output_only = Some(OutputOnly::Folders);
             Ok(())
         })),
+        Option::new("", "--sort", "Output files and folders sorted by KEY, ascending: path, mtime, matches or size [walk order]", Handler::Args1("KEY", |options, key|{
+            options.sort_opt = Some(parse_sort_key(key)?);
+            options.sort_descending = false;
+            Ok(())
+        })),
+        Option::new("", "--sortr", "Output files and folders sorted by KEY, descending", Handler::Args1("KEY", |options, key|{
+            options.sort_opt = Some(parse_sort_key(key)?);
+            options.sort_descending = true;
+            Ok(())
+        })),
         Option::new("-m", "--match-only", "Output only matches [false]", Handler::Args0(|options|{
             options.output_only = Some(OutputOnly::Match);
             Ok(())
@@ -116,6 +288,26 @@ fn generate_option_vec() -> Vec<Option> {
             options.null_separated_output = true;
             Ok(())
         })),
+        Option::new("-g", "--group", "Output only capture GROUP of each match, by index or name (or)", Handler::Args1("GROUP", |options, group|{
+            options.group_vec.push(group.to_string());
+            Ok(())
+        })),
+        Option::new("", "--group-separator", "Join multiple capture groups with STRING [tab]", Handler::Args1("STRING", |options, separator|{
+            options.group_separator = separator.to_string();
+            Ok(())
+        })),
+        Option::new("", "--group-template", "Output TEMPLATE for each match, with capture groups as {1} or {name}", Handler::Args1("TEMPLATE", |options, template|{
+            options.group_template_opt = Some(template.to_string());
+            Ok(())
+        })),
+        Option::new("", "--template", "Output TEMPLATE for each match, with fields {path}, {line}, {col}, {match}, {group:1}, {text} and {replacement}", Handler::Args1("TEMPLATE", |options, template|{
+            options.template_opt = Some(template.to_string());
+            Ok(())
+        })),
+        Option::new("", "--line-template", "Output TEMPLATE for each line, match fields refer to the first match on the line", Handler::Args1("TEMPLATE", |options, template|{
+            options.line_template_opt = Some(template.to_string());
+            Ok(())
+        })),
         Option::new("-u", "--hidden-files", "Search hidden files as well [false]", Handler::Args0(|options|{
             options.search_hidden_files = true;
             Ok(())
@@ -144,6 +336,32 @@ fn generate_option_vec() -> Vec<Option> {
             options.simulate_replace = true;
             Ok(())
         })),
+        Option::new("", "--eol", "Line endings for files with replacements: preserve, lf or crlf [preserve]", Handler::Args1("MODE", |options, mode|{
+            options.line_ending = match mode {
+                "preserve" => LineEnding::Preserve,
+                "lf" => LineEnding::Lf,
+                "crlf" => LineEnding::CrLf,
+                _ => fail!("Unknown line ending mode '{}', use preserve, lf or crlf", mode),
+            };
+            Ok(())
+        })),
+        Option::new("", "--separator", "Use SEPARATOR between input records: newline, null, paragraph, a single character or a hex byte like 0x1e [newline]", Handler::Args1("SEPARATOR", |options, separator|{
+            options.separator = parse_separator(separator)?;
+            Ok(())
+        })),
+        Option::new("", "--stream-size", "Search files larger than SIZE record per record instead of loading them, eg, 512K or 1G [64M]", Handler::Args1("SIZE", |options, size|{
+            options.stream_threshold = parse_size(size)?;
+            Ok(())
+        })),
+        Option::new("", "--mmap", "Memory-map files when only searching: never, auto or always [never]", Handler::Args1("MODE", |options, mode|{
+            options.mmap_mode = match mode {
+                "never" => MmapMode::Never,
+                "auto" => MmapMode::Auto,
+                "always" => MmapMode::Always,
+                _ => fail!("Unknown mmap mode '{}', use never, auto or always", mode),
+            };
+            Ok(())
+        })),
         Option::new("-w", "--word", "Search for word boundary [false]", Handler::Args0(|options|{
             options.word_boundary = true;
             Ok(())
@@ -164,6 +382,15 @@ fn generate_option_vec() -> Vec<Option> {
             options.search_binary_files = true;
             Ok(())
         })),
+        Option::new("-z", "--search-zip", "Search in gzip, xz and zstd compressed files and in zip and tar archives as well [false]", Handler::Args0(|options|{
+            options.search_compressed = true;
+            Ok(())
+        })),
+        Option::new("", "--encoding", "Decode input without BOM from ENCODING, eg, latin1 or utf-16le. Replacements are encoded back. [BOM detection]", Handler::Args1("ENCODING", |options, encoding|{
+            crate::encoding::from_label(encoding)?;
+            options.encoding_opt = Some(encoding.to_string());
+            Ok(())
+        })),
         Option::new("-f", "--include-filepath", "Add PATTERN to select files (and)", Handler::Args1("PATTERN", |options, pattern|{
             options.file_include_pattern_vec.push(pattern.to_string());
             Ok(())
@@ -172,6 +399,10 @@ fn generate_option_vec() -> Vec<Option> {
             options.file_exclude_pattern_vec.push(pattern.to_string());
             Ok(())
         })),
+        Option::new("", "--passthrough", "Output all lines of matching files and Stdin, highlighting the matches [false]", Handler::Args0(|options|{
+            options.passthrough = true;
+            Ok(())
+        })),
         Option::new("-A", "--output-after", "Output NUMBER lines after each match [0]", Handler::Args1("NUMBER", |options, number|{
             options.output_after = number.parse()?;
             Ok(())
@@ -180,6 +411,14 @@ fn generate_option_vec() -> Vec<Option> {
             options.output_before = number.parse()?;
             Ok(())
         })),
+        Option::new("-M", "--max-columns", "Shorten output lines of more than NUMBER characters, 0 means no limit [0]", Handler::Args1("NUMBER", |options, number|{
+            options.max_columns = number.parse()?;
+            Ok(())
+        })),
+        Option::new("", "--match-window", "Show NUMBER characters around each match of lines that are shortened via --max-columns, instead of their start", Handler::Args1("NUMBER", |options, number|{
+            options.match_window_opt = Some(number.parse()?);
+            Ok(())
+        })),
         Option::new("-i", "--input-file", "Take input from file, override auto-detection", Handler::Args0(|options|{
             options.input_from_file_opt = Some(true);
             Ok(())
@@ -188,6 +427,10 @@ fn generate_option_vec() -> Vec<Option> {
             options.input_from_file_opt = Some(false);
             Ok(())
         })),
+        Option::new("-T", "--files-from", "Search the newline- or NULL-separated filepaths from FILE, use '-' for Stdin", Handler::Args1("FILE", |options, file|{
+            options.files_from_opt = Some(file.to_string());
+            Ok(())
+        })),
         Option::new("-c", "--console-output", "Produce console or compact output [false if output stream is TTY]", Handler::Args1("BOOLEAN", |options, boolean|{
             options.console_output = Some(parse_boolean(boolean));
             Ok(())
@@ -196,10 +439,78 @@ fn generate_option_vec() -> Vec<Option> {
             options.color_output = Some(parse_boolean(boolean));
             Ok(())
         })),
-        Option::new("-o", "--open", "Open files in editor", Handler::Args0(|options|{
+        Option::new("", "--theme", "Color theme NAME: dark, light, high-contrast or monochrome-bold [$MO_THEME or dark]", Handler::Args1("NAME", |options, name|{
+            crate::theme::Theme::preset(name)?;
+            options.theme_opt = Some(name.to_string());
+            Ok(())
+        })),
+        Option::new("", "--colors", "Override theme colors with OVERRIDES like 'path=blue+bold:match=black+on_yellow', on top of $MO_COLORS", Handler::Args1("OVERRIDES", |options, overrides|{
+            crate::theme::Theme::default().apply(overrides)?;
+            options.colors_vec.push(overrides.to_string());
+            Ok(())
+        })),
+        Option::new("", "--hyperlink", "Link printed paths to FORMAT when output is a TTY: file, vscode or a URL template with {path}, {line}, {col} and {host} [none]", Handler::Args1("FORMAT", |options, format|{
+            if format != "none" {
+                crate::hyperlink::Hyperlink::new(format)?;
+            }
+            options.hyperlink_opt = Some(format.to_string());
+            Ok(())
+        })),
+        Option::new("-o", "--open", "Open files in editor, at their first match", Handler::Args0(|options|{
             options.open = true;
             Ok(())
         })),
+        Option::new("", "--pick", "Pick from the files, folders or matches interactively, and output or open them [false]", Handler::Args0(|options|{
+            options.pick = true;
+            Ok(())
+        })),
+        Option::new("", "--tui", "Search and replace interactively in a full-screen terminal UI [false]", Handler::Args0(|options|{
+            options.tui = true;
+            Ok(())
+        })),
+        Option::new("", "--watch", "Keep watching the roots and search changed files again [false]", Handler::Args0(|options|{
+            options.watch = true;
+            Ok(())
+        })),
+        Option::new("", "--watch-diff", "Keep watching the roots and output only the matches that appeared or disappeared [false]", Handler::Args0(|options|{
+            options.watch = true;
+            options.watch_diff = true;
+            Ok(())
+        })),
+        Option::new("", "--open-with", "Open files with COMMAND, using {editor}, {path}, {line}, {col} and {quickfix} [$MO_OPEN or based on $EDITOR]", Handler::Args1("COMMAND", |options, command|{
+            crate::editor::Command::new(command)?;
+            options.open = true;
+            options.open_with_opt = Some(command.to_string());
+            Ok(())
+        })),
+        Option::new("", "--ignore-file", "Add FILE with gitignore rules to skip paths (or), on top of .gitignore, .ignore and .moignore", Handler::Args1("FILE", |options, file|{
+            options.ignore_file_vec.push(file.to_string());
+            Ok(())
+        })),
+        Option::new("", "--follow-links", "Follow symlinks to files and folders, loops are skipped [false]", Handler::Args0(|options|{
+            options.follow_links = true;
+            Ok(())
+        })),
+        Option::new("", "--one-file-system", "Do not cross file system boundaries [false]", Handler::Args0(|options|{
+            options.same_file_system = true;
+            Ok(())
+        })),
+        Option::new("", "--why-ignored", "Explain which ignore rule or filter skips PATH", Handler::Args1("PATH", |options, path|{
+            options.why_ignored_vec.push(path.to_string());
+            Ok(())
+        })),
+        Option::new("", "--index", "Maintain the trigram index of each root folder that speeds up searching: build, update, info or drop", Handler::Args1("COMMAND", |options, command|{
+            options.index_command_opt = Some(parse_index_command(command)?);
+            Ok(())
+        })),
+        Option::new("", "--profile", "Apply the settings of profile NAME from the config files", Handler::Args1("NAME", |options, name|{
+            options.profile_opt = Some(name.to_string());
+            Ok(())
+        })),
+        Option::new("", "--no-config", "Ignore the user and project config files [false]", Handler::Args0(|options|{
+            options.skip_config = true;
+            Ok(())
+        })),
         ]
 }
 //</Specific part of CLI handling>
@@ -217,6 +528,83 @@ pub fn args() -> Args {
     std::env::args().skip(1).collect()
 }
 
+//Walks `args` the way parse() does, without handling any option.
+//`f` receives the option, None for a positional argument, and the arguments that it takes, starting with the option itself.
+fn walk_<'a>(args: &'a Args, mut f: impl FnMut(std::option::Option<&Option>, &[&'a String])) {
+    let options = generate_option_vec();
+    let mut args = args.iter();
+    while let Some(arg0) = args.next() {
+        match options.iter().find(|option| option.suit(arg0)) {
+            None => f(None, &[arg0]),
+            Some(option) => match option.handler {
+                Handler::Args0(_) => f(Some(option), &[arg0]),
+                Handler::Args1(..) => match args.next() {
+                    None => f(Some(option), &[arg0]),
+                    Some(arg1) => f(Some(option), &[arg0, arg1]),
+                },
+            },
+        }
+    }
+}
+
+//Returns the argument of each occurrence of the option with longhand `lh`, without handling any option.
+//Options without argument result in an empty String.
+pub fn peek(args: &Args, lh: &str) -> Vec<String> {
+    let mut values = vec![];
+    walk_(args, |option_opt, taken| match option_opt {
+        Some(option) if option.lh == lh => match option.handler {
+            Handler::Args0(_) => values.push(String::new()),
+            Handler::Args1(..) => values.extend(taken.get(1).map(|arg| arg.to_string())),
+        },
+        _ => {}
+    });
+    values
+}
+
+//Removes each occurrence of the option with longhand `lh`, together with its argument. Returns true if any was removed.
+pub fn remove(args: &mut Args, lh: &str) -> bool {
+    let mut kept = Args::new();
+    let mut removed = false;
+    walk_(args, |option_opt, taken| match option_opt {
+        Some(option) if option.lh == lh => removed = true,
+        _ => kept.extend(taken.iter().map(|arg| arg.to_string())),
+    });
+    *args = kept;
+    removed
+}
+
+//Longhands of the options in `args`, in the order they appear
+pub fn longhands(args: &Args) -> Vec<&'static str> {
+    let mut longhands = vec![];
+    walk_(args, |option_opt, _| longhands.extend(option_opt.map(|option| option.lh)));
+    longhands
+}
+
+//Removes each `--no-OPTION` from `args` and returns the longhands of these OPTIONs, eg, `--hidden-files` for
+//`--no-hidden-files`. Arguments of the form `--no-OPTION` that do not refer to an option are kept.
+pub fn take_negated(args: &mut Args) -> Vec<&'static str> {
+    let options = generate_option_vec();
+    let mut kept = Args::new();
+    let mut negated = vec![];
+    walk_(args, |option_opt, taken| {
+        let negated_opt = match option_opt {
+            None => taken[0].strip_prefix("--no-").and_then(|name| {
+                options
+                    .iter()
+                    .find(|option| option.lh.strip_prefix("--") == Some(name))
+                    .map(|option| option.lh)
+            }),
+            Some(_) => None,
+        };
+        match negated_opt {
+            None => kept.extend(taken.iter().map(|arg| arg.to_string())),
+            Some(lh) => negated.push(lh),
+        }
+    });
+    *args = kept;
+    negated
+}
+
 impl Options {
     pub fn new() -> Options {
         Options::default()
@@ -252,8 +640,16 @@ impl Options {
         {
             let extension_sets = self.extension_sets.clone();
             for extension_set in extension_sets.iter() {
+                let custom_extension_set = self
+                    .custom_extension_sets
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| extension_set == name.as_str())
+                    .cloned();
                 let mut add_extension = |extension| self.extensions.push(OsString::from(extension));
-                if extension_set == &OsString::from("c") {
+                if let Some((_, extensions)) = custom_extension_set {
+                    extensions.iter().for_each(|extension| add_extension(extension.as_str()));
+                } else if extension_set == &OsString::from("c") {
                     add_extension("c");
                     add_extension("h");
                     add_extension("cpp");
@@ -264,6 +660,19 @@ impl Options {
             }
         }
 
+        //Decompressed content cannot be written back, this is rejected before any file is touched
+        if self.search_compressed && self.replace_opt.is_some() && !self.simulate_replace {
+            fail!("Replacing is not supported with --search-zip, use --simulate to show the replacements");
+        }
+        //Archive members are reported via virtual paths that an editor cannot open
+        if self.search_compressed && self.open {
+            fail!("Opening files is not supported with --search-zip");
+        }
+        //Picking only searches, the replacements can be previewed
+        if self.pick && self.replace_opt.is_some() && !self.simulate_replace {
+            fail!("Replacing is not supported with --pick, use --simulate to preview the replacements");
+        }
+
         Ok(())
     }
 
@@ -285,6 +694,7 @@ impl Options {
             "mo --OPTION* PATTERN? --OPTION*".green()
         ));
         s.push_str("Dashed options and search PATTERN can be mixed\n");
+        s.push_str("Use --no-OPTION to turn off an OPTION that is set by a config file\n");
 
         s.push_str(&format!("{}:\n", "Options".yellow()));
         let option_vec = generate_option_vec();
@@ -303,7 +713,7 @@ impl Options {
             .unwrap();
         for o in option_vec.iter() {
             s.push_str(&o.help(max_sh_len, max_lh_len, max_name_len));
-            s.push_str("\n");
+            s.push('\n');
         }
 
         s.push_str(&format!(
@@ -333,13 +743,12 @@ struct Option {
 
 impl Option {
     fn new(sh: &'static str, lh: &'static str, descr: &'static str, handler: Handler) -> Option {
-        let o = Option {
+        Option {
             sh,
             lh,
             descr,
             handler,
-        };
-        o
+        }
     }
 
     fn help(&self, max_sh_len: usize, max_lh_len: usize, name_len: usize) -> String {
@@ -347,9 +756,11 @@ impl Option {
             Handler::Args1(name, _) => name,
             _ => "",
         };
+        let sep = if self.sh.is_empty() { " " } else { "|" };
         format!(
-            "    {:max_sh_len$}|{:max_lh_len$} {:name_len$}    {}",
+            "    {:max_sh_len$}{}{:max_lh_len$} {:name_len$}    {}",
             self.sh.yellow(),
+            sep,
             self.lh.yellow(),
             name.blue(),
             self.descr,
@@ -360,7 +771,8 @@ impl Option {
     }
 
     fn suit(&self, arg: &str) -> bool {
-        self.sh == arg || self.lh == arg
+        //Options without shorthand notation can only be matched via their longhand
+        (!self.sh.is_empty() && self.sh == arg) || self.lh == arg
     }
 }
 
@@ -458,6 +870,182 @@ fn test_options_parse() {
                 ..Options::default()
             },
         },
+        //Options without shorthand
+        Scn {
+            args: vec!["--ignore-file", "FILE", "--why-ignored", "PATH"],
+            parse_ok: true,
+            options: Options {
+                ignore_file_vec: vec![String::from("FILE")],
+                why_ignored_vec: vec![String::from("PATH")],
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--index", "update", "-C", "FOLDER"],
+            parse_ok: true,
+            options: Options {
+                index_command_opt: Some(IndexCommand::Update),
+                roots: vec![String::from("FOLDER")],
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--profile", "web", "--no-config"],
+            parse_ok: true,
+            options: Options {
+                profile_opt: Some(String::from("web")),
+                skip_config: true,
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--separator", "null"],
+            parse_ok: true,
+            options: Options {
+                separator: Separator::Byte(0x00),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--separator", "0x1e"],
+            parse_ok: true,
+            options: Options {
+                separator: Separator::Byte(0x1e),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--separator", "paragraph"],
+            parse_ok: true,
+            options: Options {
+                separator: Separator::Paragraph,
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--stream-size", "512K"],
+            parse_ok: true,
+            options: Options {
+                stream_threshold: 512 * 1024,
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--mmap", "auto"],
+            parse_ok: true,
+            options: Options {
+                mmap_mode: MmapMode::Auto,
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--passthrough"],
+            parse_ok: true,
+            options: Options {
+                passthrough: true,
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["-g", "1", "-g", "name", "--group-separator", ","],
+            parse_ok: true,
+            options: Options {
+                group_vec: vec![String::from("1"), String::from("name")],
+                group_separator: String::from(","),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--template", "{path}:{line}"],
+            parse_ok: true,
+            options: Options {
+                template_opt: Some(String::from("{path}:{line}")),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--theme", "light", "--colors", "match=bold"],
+            parse_ok: true,
+            options: Options {
+                theme_opt: Some(String::from("light")),
+                colors_vec: vec![String::from("match=bold")],
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--hyperlink", "vscode"],
+            parse_ok: true,
+            options: Options {
+                hyperlink_opt: Some(String::from("vscode")),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["-M", "120", "--match-window", "20"],
+            parse_ok: true,
+            options: Options {
+                max_columns: 120,
+                match_window_opt: Some(20),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--sort", "path", "--sortr", "mtime"],
+            parse_ok: true,
+            options: Options {
+                sort_opt: Some(SortKey::Mtime),
+                sort_descending: true,
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--pick", "-o"],
+            parse_ok: true,
+            options: Options {
+                pick: true,
+                open: true,
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--tui", "needle", "-r", "naald"],
+            parse_ok: true,
+            options: Options {
+                tui: true,
+                search_pattern_opt: Some(String::from("needle")),
+                replace_opt: Some(String::from("naald")),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--watch-diff", "needle"],
+            parse_ok: true,
+            options: Options {
+                watch: true,
+                watch_diff: true,
+                search_pattern_opt: Some(String::from("needle")),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["--open-with", "vim -q {quickfix}"],
+            parse_ok: true,
+            options: Options {
+                open: true,
+                open_with_opt: Some(String::from("vim -q {quickfix}")),
+                ..Options::default()
+            },
+        },
+        Scn {
+            args: vec!["-z", "-r", "X", "-n"],
+            parse_ok: true,
+            options: Options {
+                search_compressed: true,
+                replace_opt: Some(String::from("X")),
+                simulate_replace: true,
+                ..Options::default()
+            },
+        },
         //All options
         Scn {
             args: vec!["-h", "-C", "ROOT"],
@@ -477,6 +1065,66 @@ fn test_options_parse() {
                 ..Options::default()
             },
         },
+        Scn {
+            args: vec!["--why-ignored"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--separator", "ab"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--open-with", "vim +{nr} {path}"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--index", "rebuild"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--sort", "name"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["-z", "-r", "X"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--pick", "-r", "X"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["-z", "-o"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--stream-size", "20000000000G"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--theme", "solarized"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--hyperlink", "file://{file}"],
+            parse_ok: false,
+            options: Options::default(),
+        },
+        Scn {
+            args: vec!["--stream-size", "1T"],
+            parse_ok: false,
+            options: Options::default(),
+        },
         Scn {
             args: vec!["-C", "-h"],
             parse_ok: true,
@@ -501,4 +1149,28 @@ fn test_options_parse() {
         }
     }
 }
+
+#[test]
+fn test_peek() {
+    let args: Args = ["-C", "--profile", "-r", "--profile", "--no-config", "--root", "src"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    assert_eq!(peek(&args, "--root"), vec!["--profile", "src"]);
+    assert_eq!(peek(&args, "--replace"), vec!["--profile"]);
+    assert_eq!(peek(&args, "--no-config"), vec![""]);
+    assert!(peek(&args, "--profile").is_empty());
+
+    let mut args: Args = ["-u", "needle", "--no-hidden-files", "--no-such-option", "-C", "ROOT", "-f"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    assert_eq!(longhands(&args), vec!["--hidden-files", "--root", "--include-filepath"]);
+    assert_eq!(take_negated(&mut args), vec!["--hidden-files"]);
+    assert_eq!(args, vec!["-u", "needle", "--no-such-option", "-C", "ROOT", "-f"]);
+    assert!(remove(&mut args, "--root"));
+    assert!(remove(&mut args, "--include-filepath"));
+    assert!(!remove(&mut args, "--root"));
+    assert_eq!(args, vec!["-u", "needle", "--no-such-option"]);
+}
 //</Generic part of CLI handling>
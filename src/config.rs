@@ -0,0 +1,372 @@
+use crate::cli::{self, Args, Options};
+use crate::util::{MyError, Result};
+use std::path::{Path, PathBuf};
+
+//Name of the project config file, the nearest one in the root folder or its parents is used
+pub const PROJECT_FILENAME: &str = ".moconfig";
+
+//Options that a project config can set via args: these only select files or change the layout of the output.
+//A project might not be trusted, options that write files or run commands are ignored.
+const PROJECT_OPTIONS: &[&str] = &[
+    "--sort",
+    "--sortr",
+    "--hidden-files",
+    "--ignored-files",
+    "--word",
+    "--sensitive",
+    "--extension",
+    "--extension-set",
+    "--binary",
+    "--search-zip",
+    "--encoding",
+    "--include-filepath",
+    "--exclude-filepath",
+    "--ignore-file",
+    "--follow-links",
+    "--one-file-system",
+    "--stream-size",
+    "--mmap",
+    "--output-after",
+    "--output-before",
+    "--max-columns",
+    "--match-window",
+    "--console-output",
+    "--color-output",
+    "--theme",
+    "--colors",
+    "--hyperlink",
+];
+
+//Settings from the top of a config file, or from one of its profiles
+#[derive(Debug, Default, PartialEq)]
+struct Settings {
+    args: Vec<String>,
+    theme_opt: Option<String>,
+    colors_vec: Vec<String>,
+    editor_opt: Option<String>,
+}
+
+//Config files in the INI-like format below, later files take precedence.
+//Arguments are separated by whitespace, use quotes for arguments that contain whitespace.
+//The command to open files can only be set in the user config, a project config can only set PROJECT_OPTIONS.
+//
+//  #Comment
+//  args = -u -F /build/ -f 'test data/'
+//  theme = light
+//  colors = path=blue+bold
+//  editor = nvim +{line} {path}
+//
+//  [extension-sets]
+//  cpp = c h cpp hpp
+//
+//  [profile web]
+//  args = -e js -e ts
+#[derive(Debug, Default)]
+pub struct Config {
+    pub paths: Vec<PathBuf>,
+    //Settings per profile, None for the settings that always apply
+    sections: Vec<(Option<String>, Settings)>,
+    extension_sets: Vec<(String, Vec<String>)>,
+}
+
+impl Config {
+    //Loads the user config and the project config that is found upward from the first root
+    pub fn load(roots: &[String]) -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(path) = user_path_() {
+            config.load_file_(&path, true)?;
+        }
+        let root = roots.first().map_or(".", |root| root.as_str());
+        if let Ok(root) = std::fs::canonicalize(root) {
+            if let Some(path) = root
+                .ancestors()
+                .map(|folder| folder.join(PROJECT_FILENAME))
+                .find(|path| path.is_file())
+            {
+                config.load_file_(&path, false)?;
+            }
+        }
+        Ok(config)
+    }
+
+    fn load_file_(&mut self, path: &Path, is_user: bool) -> Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let content = match std::fs::read_to_string(path) {
+            Err(err) => fail!("Could not read config '{}': {}", path.display(), err),
+            Ok(content) => content,
+        };
+        self.parse_(path, &content, is_user)?;
+        self.paths.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn parse_(&mut self, path: &Path, content: &str, is_user: bool) -> Result<()> {
+        //Section that is being parsed: None for the top, Some(None) for the extension sets, Some(Some(name)) for a profile
+        let mut section: Option<Option<String>> = None;
+        self.sections.push((None, Settings::default()));
+        for (ix, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let location = format!("{}:{}", path.display(), ix + 1);
+
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let words: Vec<&str> = header.split_whitespace().collect();
+                section = match words.as_slice() {
+                    ["extension-sets"] => Some(None),
+                    ["profile", name] => {
+                        self.sections
+                            .push((Some(name.to_string()), Settings::default()));
+                        Some(Some(name.to_string()))
+                    }
+                    _ => fail!("{}: Unknown section '{}'", location, line),
+                };
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                None => fail!("{}: Expected 'key = value', got '{}'", location, line),
+                Some((key, value)) => (key.trim(), value.trim()),
+            };
+            if section == Some(None) {
+                let extensions = value.split_whitespace().map(|s| s.to_string()).collect();
+                self.extension_sets.push((key.to_string(), extensions));
+                continue;
+            }
+            let settings = &mut self.sections.last_mut().unwrap().1;
+            match key {
+                "args" => {
+                    let mut args = split_args_(value, &location)?;
+                    if !is_user {
+                        for lh in cli::longhands(&args) {
+                            if !PROJECT_OPTIONS.contains(&lh) && cli::remove(&mut args, lh) {
+                                eprintln!(
+                                    "Warning: {}: Ignoring {}, a project config can only set options that select files or change the output layout",
+                                    location, lh
+                                );
+                            }
+                        }
+                    }
+                    settings.args.extend(args);
+                }
+                "theme" => {
+                    if let Err(err) = crate::theme::Theme::preset(value) {
+                        fail!("{}: {}", location, err);
+                    }
+                    settings.theme_opt = Some(value.to_string());
+                }
+                "colors" => {
+                    if let Err(err) = crate::theme::Theme::default().apply(value) {
+                        fail!("{}: {}", location, err);
+                    }
+                    settings.colors_vec.push(value.to_string());
+                }
+                "editor" => {
+                    if let Err(err) = crate::editor::Command::new(value) {
+                        fail!("{}: {}", location, err);
+                    }
+                    if is_user {
+                        settings.editor_opt = Some(value.to_string());
+                    } else {
+                        eprintln!(
+                            "Warning: {}: Ignoring editor, only the user config can set the command to open files",
+                            location
+                        );
+                    }
+                }
+                _ => fail!("{}: Unknown key '{}'", location, key),
+            }
+        }
+        Ok(())
+    }
+
+    //Sets the defaults from the config and `profile_opt` in `options`.
+    //Returns the config arguments, these should be parsed before the CLI arguments to allow overriding them.
+    pub fn apply(&self, profile_opt: Option<&str>, options: &mut Options) -> Result<Args> {
+        if let Some(profile) = profile_opt {
+            if !self
+                .sections
+                .iter()
+                .any(|(name_opt, _)| name_opt.as_deref() == Some(profile))
+            {
+                fail!("Unknown profile '{}'", profile);
+            }
+        }
+        //Profile settings take precedence over the settings that always apply
+        let selected = self
+            .sections
+            .iter()
+            .filter(|(name_opt, _)| name_opt.is_none())
+            .chain(self.sections.iter().filter(|(name_opt, _)| {
+                profile_opt.is_some() && name_opt.as_deref() == profile_opt
+            }));
+        let mut args = Args::new();
+        for (_, settings) in selected {
+            args.extend(settings.args.iter().cloned());
+            if settings.theme_opt.is_some() {
+                options.theme_opt = settings.theme_opt.clone();
+            }
+            options
+                .colors_vec
+                .extend(settings.colors_vec.iter().cloned());
+            if settings.editor_opt.is_some() {
+                options.open_with_opt = settings.editor_opt.clone();
+            }
+        }
+        options
+            .custom_extension_sets
+            .extend(self.extension_sets.iter().cloned());
+        Ok(args)
+    }
+}
+
+//Turns off the option with longhand `lh` that was set by the config: it is removed from the config arguments
+//`args` and, for --theme, --colors and --open-with, reset in `options`. Fails when the config did not set it.
+pub fn turn_off(lh: &str, args: &mut Args, options: &mut Options) -> Result<()> {
+    let mut found = cli::remove(args, lh);
+    match lh {
+        "--theme" => found |= options.theme_opt.take().is_some(),
+        "--colors" => found |= options.colors_vec.drain(..).count() > 0,
+        "--open-with" => found |= options.open_with_opt.take().is_some(),
+        _ => {}
+    }
+    if !found {
+        fail!(
+            "Nothing to turn off for --no-{}, {} is not set by a config file",
+            lh.trim_start_matches('-'),
+            lh
+        );
+    }
+    Ok(())
+}
+
+//Splits `value` on whitespace. Single quotes keep everything up to the next single quote,
+//within double quotes and outside quotes, a backslash escapes the next character.
+fn split_args_(value: &str, location: &str) -> Result<Args> {
+    let mut args = Args::new();
+    //Argument that is being parsed, None in between arguments
+    let mut arg_opt: Option<String> = None;
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch.is_whitespace() {
+            args.extend(arg_opt.take());
+            continue;
+        }
+        let arg = arg_opt.get_or_insert_with(String::new);
+        let quote_opt = match ch {
+            '\'' | '"' => Some(ch),
+            '\\' => {
+                arg.extend(chars.next());
+                None
+            }
+            _ => {
+                arg.push(ch);
+                None
+            }
+        };
+        if let Some(quote) = quote_opt {
+            loop {
+                match chars.next() {
+                    None => fail!("{}: Missing closing quote in '{}'", location, value),
+                    Some(ch) if ch == quote => break,
+                    Some('\\') if quote == '"' => arg.extend(chars.next()),
+                    Some(ch) => arg.push(ch),
+                }
+            }
+        }
+    }
+    args.extend(arg_opt);
+    Ok(args)
+}
+
+fn user_path_() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("mo").join("config"))
+}
+
+#[test]
+fn test_config() -> Result<()> {
+    let user = "
+        #Always applied
+        args = -u -F /build/ -f 'my data/'
+        theme = light
+        editor = nvim +{line} {path}
+
+        [extension-sets]
+        cpp = c h cpp hpp
+
+        [profile web]
+        args = -e js
+        colors = path=blue
+    ";
+    //Options that write files or run commands are ignored in a project config
+    let project = r#"
+        args = -s --open-with "code {path}" -r X -o --index build -T list.txt --watch --tui --pick -V
+        editor = code {path}
+        [profile web]
+        args = -e ts -f a\ b
+    "#;
+    let mut config = Config::default();
+    config.parse_(Path::new("user"), user, true)?;
+    config.parse_(Path::new("project"), project, false)?;
+
+    let mut options = Options::default();
+    let args = config.apply(None, &mut options)?;
+    assert_eq!(args, vec!["-u", "-F", "/build/", "-f", "my data/", "-s"]);
+    assert_eq!(options.theme_opt, Some(String::from("light")));
+    assert!(options.colors_vec.is_empty());
+    assert_eq!(
+        options.open_with_opt,
+        Some(String::from("nvim +{line} {path}"))
+    );
+    assert!(!options.open);
+
+    let mut options = Options::default();
+    let args = config.apply(Some("web"), &mut options)?;
+    assert_eq!(
+        args,
+        vec!["-u", "-F", "/build/", "-f", "my data/", "-s", "-e", "js", "-e", "ts", "-f", "a b"]
+    );
+    assert_eq!(options.colors_vec, vec!["path=blue"]);
+
+    options.parse(vec!["-E", "cpp"].into_iter().map(String::from).collect())?;
+    assert_eq!(options.extensions, vec!["c", "h", "cpp", "hpp"]);
+
+    assert!(config.apply(Some("mobile"), &mut options).is_err());
+
+    //Turning off options from the config
+    let mut options = Options::default();
+    let mut args = config.apply(Some("web"), &mut options)?;
+    turn_off("--include-filepath", &mut args, &mut options)?;
+    turn_off("--theme", &mut args, &mut options)?;
+    turn_off("--colors", &mut args, &mut options)?;
+    assert_eq!(
+        args,
+        vec!["-u", "-F", "/build/", "-s", "-e", "js", "-e", "ts"]
+    );
+    assert_eq!(options.theme_opt, None);
+    assert!(options.colors_vec.is_empty());
+    assert!(turn_off("--colors", &mut args, &mut options).is_err());
+    assert!(turn_off("--word", &mut args, &mut options).is_err());
+    for content in [
+        "[profile]",
+        "args -u",
+        "color = red",
+        "editor = {file}",
+        "args = -f 'a",
+        "theme = solarized",
+    ]
+    .iter()
+    {
+        assert!(Config::default()
+            .parse_(Path::new("bad"), content, true)
+            .is_err());
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+
+pub enum Event<T> {
+    Record(T),
+    //Indicates a gap between two groups of output records
+    Separator,
+}
+
+//Decides which records to output when context is requested around matching records.
+//Only the records that might still be needed as before-context are kept, keeping memory bounded.
+pub struct Context<T> {
+    before: usize,
+    after: usize,
+    pending: VecDeque<T>,
+    after_left: usize,
+    has_output: bool,
+    has_gap: bool,
+}
+
+impl<T> Context<T> {
+    pub fn new(before: u64, after: u64) -> Context<T> {
+        let before = before as usize;
+        Context {
+            before,
+            after: after as usize,
+            pending: VecDeque::with_capacity(before),
+            after_left: 0,
+            has_output: false,
+            has_gap: false,
+        }
+    }
+
+    //Feeds the next record, `output` is called for each record or separator that should be output now
+    pub fn push<F>(&mut self, record: T, is_match: bool, mut output: F)
+    where
+        F: FnMut(Event<T>),
+    {
+        if is_match {
+            if self.has_output && self.has_gap {
+                output(Event::Separator);
+            }
+            for record in self.pending.drain(..) {
+                output(Event::Record(record));
+            }
+            output(Event::Record(record));
+            self.after_left = self.after;
+            self.has_output = true;
+            self.has_gap = false;
+        } else if self.after_left > 0 {
+            output(Event::Record(record));
+            self.after_left -= 1;
+        } else {
+            self.pending.push_back(record);
+            if self.pending.len() > self.before {
+                self.pending.pop_front();
+                self.has_gap = true;
+            }
+        }
+    }
+
+    //Indicates that records were skipped, none of them are part of the context of a match
+    pub fn skip(&mut self) {
+        self.pending.clear();
+        self.after_left = 0;
+        self.has_gap = true;
+    }
+}
+
+#[test]
+fn test_context() {
+    struct Scn {
+        before: u64,
+        after: u64,
+        matches: &'static str,
+        output: &'static str,
+    }
+    let scns = [
+        Scn {
+            before: 0,
+            after: 0,
+            matches: "..x.x..xx.",
+            output: "2|4|78",
+        },
+        Scn {
+            before: 1,
+            after: 1,
+            matches: "..x.x....xx.",
+            output: "12345|891011",
+        },
+        Scn {
+            before: 2,
+            after: 0,
+            matches: "x..x",
+            output: "0123",
+        },
+        Scn {
+            before: 0,
+            after: 2,
+            matches: "x.....x",
+            output: "012|6",
+        },
+    ];
+
+    for scn in scns.iter() {
+        let mut context = Context::new(scn.before, scn.after);
+        let mut output = String::new();
+        for (ix, ch) in scn.matches.chars().enumerate() {
+            context.push(ix, ch == 'x', |event| match event {
+                Event::Record(ix) => output.push_str(&ix.to_string()),
+                Event::Separator => output.push('|'),
+            });
+        }
+        assert_eq!(output, scn.output, "{}", scn.matches);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::template::{self, Part};
+use crate::util::{MyError, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+//Environment variable with the command template to open files
+pub const OPEN_ENV: &str = "MO_OPEN";
+
+//Matching line, col is the 1-based byte column of its first match
+#[derive(Debug, PartialEq, Clone)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: u64,
+    pub col: usize,
+    pub text: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Field {
+    Editor,
+    Path,
+    Line,
+    Col,
+    Quickfix,
+}
+
+//Command line to open files in an editor, eg, `{editor} +{line} {path}`.
+//Consecutive arguments with {path}, {line} or {col} are repeated for each file.
+//{quickfix} refers to a file that lists all matching lines as `path:line:col:text`.
+#[derive(Debug, PartialEq)]
+pub struct Command {
+    args: Vec<Vec<Part<Field>>>,
+}
+
+impl Command {
+    pub fn new(template: &str) -> Result<Command> {
+        let mut args = vec![];
+        for arg in template.split_whitespace() {
+            args.push(template::parse(arg, |name| {
+                let field = match name {
+                    "editor" => Field::Editor,
+                    "path" => Field::Path,
+                    "line" => Field::Line,
+                    "col" => Field::Col,
+                    "quickfix" => Field::Quickfix,
+                    _ => fail!("Unknown open field '{{{}}}'", name),
+                };
+                Ok(field)
+            })?);
+        }
+        if args.is_empty() {
+            fail!("Open command template '{}' is empty", template);
+        }
+        Ok(Command { args })
+    }
+
+    //Template that passes the location in a way that `editor` understands, based on its name.
+    //Editors that take +LINE only take it once, several files are passed via a quickfix file or without location.
+    pub fn default_template(editor: &str, file_count: usize) -> &'static str {
+        let name = editor
+            .split_whitespace()
+            .next()
+            .and_then(|program| Path::new(program).file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match name.as_str() {
+            "vim" | "nvim" | "gvim" if file_count > 1 => "{editor} -q {quickfix}",
+            "vi" | "nano" | "emacs" | "kak" if file_count > 1 => "{editor} {path}",
+            "vi" | "vim" | "nvim" | "gvim" | "nano" | "emacs" | "kak" => "{editor} +{line} {path}",
+            "hx" | "helix" | "subl" | "zed" => "{editor} {path}:{line}:{col}",
+            "code" | "codium" => "{editor} -g {path}:{line}:{col}",
+            _ => "{editor} {path}",
+        }
+    }
+
+    pub fn needs_quickfix(&self) -> bool {
+        self.args
+            .iter()
+            .any(|parts| parts.contains(&Part::Field(Field::Quickfix)))
+    }
+
+    //Returns the program and its arguments to open `files`, `editor` can contain arguments as well
+    pub fn expand(&self, editor: &str, files: &[Location], quickfix: &Path) -> Vec<String> {
+        let mut cmd = vec![];
+        let mut ix = 0;
+        while ix < self.args.len() {
+            if self.args[ix] == [Part::Field(Field::Editor)] {
+                cmd.extend(editor.split_whitespace().map(|word| word.to_string()));
+                ix += 1;
+            } else if is_per_file_(&self.args[ix]) {
+                let end = (ix..self.args.len())
+                    .find(|&end| !is_per_file_(&self.args[end]))
+                    .unwrap_or(self.args.len());
+                for file in files {
+                    for parts in &self.args[ix..end] {
+                        cmd.push(expand_(parts, editor, Some(file), quickfix));
+                    }
+                }
+                ix = end;
+            } else {
+                cmd.push(expand_(&self.args[ix], editor, None, quickfix));
+                ix += 1;
+            }
+        }
+        cmd
+    }
+}
+
+//The files to open, at their first location, if any. Files without location are opened at their start.
+pub fn files(paths: &[PathBuf], locations: &[Location]) -> Vec<Location> {
+    let mut files: Vec<Location> = vec![];
+    let location_or_start = |path: &PathBuf| match locations.iter().find(|l| &l.path == path) {
+        Some(location) => location.clone(),
+        None => Location {
+            path: path.clone(),
+            line: 1,
+            col: 1,
+            text: String::new(),
+        },
+    };
+    for path in paths {
+        if !files.iter().any(|file| &file.path == path) {
+            files.push(location_or_start(path));
+        }
+    }
+    files
+}
+
+//File that lists all locations as `path:line:col:text`, files without location are listed at their start.
+//It is created in a new folder that is only accessible by the user, and removed together with this folder when dropped.
+pub struct Quickfix {
+    folder: PathBuf,
+    pub path: PathBuf,
+}
+
+impl Quickfix {
+    pub fn create(files: &[Location], locations: &[Location]) -> Result<Quickfix> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.subsec_nanos());
+        let folder = std::env::temp_dir().join(format!("mo-{}-{}", std::process::id(), nanos));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        //Creation fails when the folder already exists, we never use a folder that was prepared by someone else
+        if let Err(err) = builder.create(&folder) {
+            fail!(
+                "Could not create folder '{}' for the quickfix file: {}",
+                folder.display(),
+                err
+            );
+        }
+        let quickfix = Quickfix {
+            path: folder.join("mo.quickfix"),
+            folder,
+        };
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&quickfix.path);
+        let mut file = match file {
+            Err(err) => fail!(
+                "Could not create quickfix file '{}': {}",
+                quickfix.path.display(),
+                err
+            ),
+            Ok(file) => std::io::BufWriter::new(file),
+        };
+        let without_location = files
+            .iter()
+            .filter(|file| !locations.iter().any(|l| l.path == file.path));
+        for l in locations.iter().chain(without_location) {
+            //Each location takes a single line, eg, when lines are separated by a custom separator
+            let text = l.text.replace(&['\n', '\r'][..], " ");
+            writeln!(file, "{}:{}:{}:{}", l.path.display(), l.line, l.col, text)?;
+        }
+        file.flush()?;
+        Ok(quickfix)
+    }
+}
+
+impl Drop for Quickfix {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.folder);
+    }
+}
+
+fn is_per_file_(parts: &[Part<Field>]) -> bool {
+    parts.iter().any(|part| {
+        matches!(
+            part,
+            Part::Field(Field::Path) | Part::Field(Field::Line) | Part::Field(Field::Col)
+        )
+    })
+}
+
+fn expand_(
+    parts: &[Part<Field>],
+    editor: &str,
+    file_opt: Option<&Location>,
+    quickfix: &Path,
+) -> String {
+    let mut arg = String::new();
+    for part in parts {
+        match part {
+            Part::Text(text) => arg.push_str(text),
+            Part::Field(field) => match (field, file_opt) {
+                (Field::Editor, _) => arg.push_str(editor),
+                (Field::Path, Some(file)) => arg.push_str(&file.path.display().to_string()),
+                (Field::Line, Some(file)) => arg.push_str(&file.line.to_string()),
+                (Field::Col, Some(file)) => arg.push_str(&file.col.to_string()),
+                (Field::Quickfix, _) => arg.push_str(&quickfix.display().to_string()),
+                (_, None) => {}
+            },
+        }
+    }
+    arg
+}
+
+#[test]
+fn test_command() -> Result<()> {
+    let location = |path: &str, line, col| Location {
+        path: PathBuf::from(path),
+        line,
+        col,
+        text: String::from("text"),
+    };
+    let paths = vec![PathBuf::from("a.rs"), PathBuf::from("b.rs")];
+    let locations = vec![location("b.rs", 5, 3), location("b.rs", 7, 1)];
+    let files = files(&paths, &locations);
+    assert_eq!(files.len(), 2);
+    assert_eq!((files[0].line, files[0].col), (1, 1));
+    assert_eq!(files[1], locations[0]);
+    let quickfix = Path::new("/tmp/mo.quickfix");
+
+    let scns = [
+        ("hx", "hx a.rs:1:1 b.rs:5:3"),
+        ("code --wait", "code --wait -g a.rs:1:1 b.rs:5:3"),
+        ("/usr/bin/ed", "/usr/bin/ed a.rs b.rs"),
+        ("nano", "nano a.rs b.rs"),
+        ("emacs -nw", "emacs -nw a.rs b.rs"),
+    ];
+    for (editor, expected) in scns.iter() {
+        let command = Command::new(Command::default_template(editor, files.len()))?;
+        assert!(!command.needs_quickfix());
+        assert_eq!(
+            command.expand(editor, &files, quickfix).join(" "),
+            *expected
+        );
+    }
+
+    //Editors take a single +LINE, vim takes a quickfix file for several files
+    for editor in ["vim", "kak"].iter() {
+        let command = Command::new(Command::default_template(editor, 1))?;
+        assert_eq!(
+            command.expand(editor, &files[1..], quickfix).join(" "),
+            format!("{} +5 b.rs", editor)
+        );
+    }
+    let command = Command::new(Command::default_template("/usr/bin/nvim", files.len()))?;
+    assert!(command.needs_quickfix());
+    assert_eq!(
+        command.expand("/usr/bin/nvim", &files, quickfix).join(" "),
+        "/usr/bin/nvim -q /tmp/mo.quickfix"
+    );
+
+    let command = Command::new("nvim -q {quickfix}")?;
+    assert!(command.needs_quickfix());
+    assert_eq!(
+        command.expand("hx", &files, quickfix),
+        vec!["nvim", "-q", "/tmp/mo.quickfix"]
+    );
+
+    assert!(Command::new("{editor} {file}").is_err());
+    assert!(Command::new(" ").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_quickfix() -> Result<()> {
+    let location = |path: &str, line| Location {
+        path: PathBuf::from(path),
+        line,
+        col: 1,
+        text: String::from("text"),
+    };
+    let mut locations = vec![location("b.rs", 5), location("b.rs", 7)];
+    locations[1].text = String::from("two\r\nlines");
+    let files = files(&[PathBuf::from("a.rs"), PathBuf::from("b.rs")], &locations);
+
+    let quickfix = Quickfix::create(&files, &locations)?;
+    let path = quickfix.path.clone();
+    assert_eq!(
+        std::fs::read_to_string(&path)?,
+        "b.rs:5:1:text\nb.rs:7:1:two  lines\na.rs:1:1:\n"
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let md = std::fs::metadata(path.parent().unwrap())?;
+        assert_eq!(md.permissions().mode() & 0o777, 0o700);
+    }
+
+    drop(quickfix);
+    assert!(!path.exists());
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use crate::util::{MyError, Result};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+//Describes how content was transcoded into UTF-8, allowing it to be encoded back
+#[derive(Debug, Clone, Copy)]
+pub struct Transcoding {
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+    //Some input could not be decoded and was substituted with U+FFFD
+    pub lossy: bool,
+}
+
+pub fn from_label(label: &str) -> Result<&'static Encoding> {
+    match Encoding::for_label(label.as_bytes()) {
+        None => fail!("Unknown encoding '{}'", label),
+        Some(encoding) => Ok(encoding),
+    }
+}
+
+//Decodes `raw` into UTF-8 when it starts with a BOM or when an encoding is forced. A BOM takes precedence.
+//Returns None when `raw` can be used as-is, `content` is left untouched in that case.
+pub fn decode(
+    raw: &[u8],
+    forced_opt: Option<&'static Encoding>,
+    content: &mut Vec<u8>,
+) -> Option<Transcoding> {
+    let (encoding, bom_len) = match Encoding::for_bom(raw) {
+        Some(found) => found,
+        None => (forced_opt?, 0),
+    };
+    if encoding == UTF_8 && bom_len == 0 {
+        return None;
+    }
+
+    content.clear();
+    let lossy = if encoding == UTF_8 {
+        //Only the BOM is stripped, invalid UTF-8 is kept as-is
+        content.extend_from_slice(&raw[bom_len..]);
+        false
+    } else {
+        let (decoded, had_errors) = encoding.decode_without_bom_handling(&raw[bom_len..]);
+        content.extend_from_slice(decoded.as_bytes());
+        had_errors
+    };
+
+    Some(Transcoding {
+        encoding,
+        bom: bom_len > 0,
+        lossy,
+    })
+}
+
+//Encodes UTF-8 `content` back into the original encoding, including its BOM
+pub fn encode(content: &[u8], transcoding: &Transcoding, output: &mut Vec<u8>) -> Result<()> {
+    let encoding = transcoding.encoding;
+
+    output.clear();
+    if transcoding.bom {
+        if encoding == UTF_16LE {
+            output.extend_from_slice(b"\xff\xfe");
+        } else if encoding == UTF_16BE {
+            output.extend_from_slice(b"\xfe\xff");
+        } else {
+            output.extend_from_slice(b"\xef\xbb\xbf");
+        }
+    }
+
+    if encoding == UTF_8 {
+        output.extend_from_slice(content);
+        return Ok(());
+    }
+
+    let content = match std::str::from_utf8(content) {
+        Err(_) => fail!(
+            "Content is not valid UTF-8 and cannot be encoded as {}",
+            encoding.name()
+        ),
+        Ok(content) => content,
+    };
+    //encoding_rs only encodes into UTF-16 for the web, we do this ourselves
+    if encoding == UTF_16LE {
+        for unit in content.encode_utf16() {
+            output.extend_from_slice(&unit.to_le_bytes());
+        }
+    } else if encoding == UTF_16BE {
+        for unit in content.encode_utf16() {
+            output.extend_from_slice(&unit.to_be_bytes());
+        }
+    } else {
+        let (encoded, _, had_errors) = encoding.encode(content);
+        if had_errors {
+            fail!(
+                "Content cannot be encoded as {} without loss",
+                encoding.name()
+            );
+        }
+        output.extend_from_slice(&encoded);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_decode_encode() -> Result<()> {
+    struct Scn {
+        raw: &'static [u8],
+        forced: Option<&'static str>,
+        content: Option<&'static str>,
+    }
+    let scns = [
+        Scn {
+            raw: b"plain\n",
+            forced: None,
+            content: None,
+        },
+        Scn {
+            raw: b"plain\n",
+            forced: Some("utf-8"),
+            content: None,
+        },
+        Scn {
+            raw: b"\xef\xbb\xbfbom\n",
+            forced: None,
+            content: Some("bom\n"),
+        },
+        Scn {
+            raw: b"\xff\xfeh\x00\xe9\x00\n\x00",
+            forced: None,
+            content: Some("h\u{e9}\n"),
+        },
+        Scn {
+            raw: b"\xfe\xff\x00h\x00\xe9\x00\n",
+            forced: Some("latin1"),
+            content: Some("h\u{e9}\n"),
+        },
+        Scn {
+            raw: b"h\xe9\n",
+            forced: Some("latin1"),
+            content: Some("h\u{e9}\n"),
+        },
+    ];
+
+    for scn in scns.iter() {
+        let forced_opt = match scn.forced {
+            None => None,
+            Some(label) => Some(from_label(label)?),
+        };
+        let mut content = vec![];
+        let transcoding_opt = decode(scn.raw, forced_opt, &mut content);
+        match scn.content {
+            None => assert!(transcoding_opt.is_none()),
+            Some(expected) => {
+                let transcoding = transcoding_opt.unwrap();
+                assert!(!transcoding.lossy);
+                assert_eq!(content, expected.as_bytes());
+
+                let mut raw = vec![];
+                encode(&content, &transcoding, &mut raw)?;
+                assert_eq!(raw, scn.raw);
+            }
+        }
+    }
+
+    assert!(from_label("no-such-encoding").is_err());
+
+    let transcoding = decode(b"h\xe9\n", Some(from_label("latin1")?), &mut vec![]).unwrap();
+    assert!(encode("\u{142}".as_bytes(), &transcoding, &mut vec![]).is_err());
+
+    Ok(())
+}
@@ -1,18 +1,92 @@
-use crate::line::{Content, Line};
-use crate::search::{Replace, Search};
+use crate::archive;
+use crate::cli::{LineEnding, MmapMode, Separator};
+use crate::editor::Location;
+use crate::encoding::{self, Transcoding};
+use crate::hyperlink::Hyperlink;
+use crate::line::{self, Content, Line};
+use crate::search::{Extract, Replace, Search};
+use crate::template::Template;
+use crate::theme::Theme;
 use crate::util::{MyError, Result};
 use std::io::Read;
 use std::io::Write;
 use std::path::PathBuf;
 
+//Files of at least this size are memory-mapped when MmapMode::Auto is used.
+//Below this size, mapping is slower than reading, see `rake perf_mmap`.
+pub const MMAP_THRESHOLD: u64 = 256 * 1024;
+
+//A file that changes while it is read is always reported, other load errors only in verbose mode
+#[derive(Debug)]
+pub struct SizeChanged {
+    pub path: PathBuf,
+}
+impl std::error::Error for SizeChanged {}
+impl std::fmt::Display for SizeChanged {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            fmt,
+            "'{}' changed size while reading, results might be incomplete",
+            self.path.display()
+        )
+    }
+}
+
+//Output of a single file, kept back to output all files sorted on their number of matches, or to preview it in the picker
+pub struct Recording {
+    pub path: PathBuf,
+    pub match_count: usize,
+    pub events: Vec<Recorded>,
+}
+pub enum Recorded {
+    Header,
+    //Lines are rebased onto their own content
+    Line(Line, Vec<u8>),
+    Separator,
+    Footer,
+    Filename,
+}
+
 pub struct Data {
     pub search_opt: Option<Search>,
     pub invert_pattern: bool,
     pub replace_opt: Option<Replace>,
+    //Output for each match instead of the complete line
+    pub extract_opt: Option<Extract>,
+    //User-defined layout for each output line
+    pub template_opt: Option<Template>,
+    pub theme: Theme,
+    //Printed paths are wrapped in terminal hyperlinks when present
+    pub hyperlink_opt: Option<Hyperlink>,
     pub path: PathBuf,
     pub content: Content,
+    //Memory-mapped file, used instead of content when present
+    mapped_opt: Option<memmap2::Mmap>,
     pub lines: Vec<Line>,
     pub filepaths: Vec<std::path::PathBuf>,
+    //Matching lines of the processed files, only collected when files are opened in an editor
+    pub locations: Vec<Location>,
+    //Output of the processed files, only collected when sorting on the number of matches or when `record_output` is set
+    pub recordings: Vec<Recording>,
+    pub record_output: bool,
+    //Transparently decompress files with a known compression extension
+    pub decompress: bool,
+    //Content was decompressed or extracted from an archive and cannot be written back
+    pub extracted: bool,
+    //Encoding used to decode files without BOM, None means no decoding
+    pub encoding_opt: Option<&'static encoding_rs::Encoding>,
+    //How the current content was decoded, None means it is used as-is
+    pub transcoding_opt: Option<Transcoding>,
+    //Line endings used when writing replacements
+    pub line_ending: LineEnding,
+    pub separator: Separator,
+    //Must be MmapMode::Never when content is replaced
+    pub mmap_mode: MmapMode,
+    //Number of context lines that are needed before and after each matching line
+    pub context_before: u64,
+    pub context_after: u64,
+    //Indicates if lines contains all lines, or only the matching lines and their context
+    lines_complete: bool,
 }
 
 impl Data {
@@ -24,11 +98,29 @@ impl Data {
         Data {
             search_opt,
             invert_pattern,
-            replace_opt: replace_opt,
+            replace_opt,
+            extract_opt: None,
+            template_opt: None,
+            theme: Theme::default(),
+            hyperlink_opt: None,
             path: PathBuf::new(),
             content: Content::new(),
+            mapped_opt: None,
             lines: vec![],
             filepaths: vec![],
+            locations: vec![],
+            recordings: vec![],
+            record_output: false,
+            decompress: false,
+            extracted: false,
+            encoding_opt: None,
+            transcoding_opt: None,
+            line_ending: LineEnding::Preserve,
+            separator: Separator::Byte(0x0a_u8),
+            mmap_mode: MmapMode::Never,
+            context_before: 0,
+            context_after: 0,
+            lines_complete: false,
         }
     }
 
@@ -37,67 +129,196 @@ impl Data {
         P: AsRef<std::path::Path>,
     {
         self.path = PathBuf::from(path.as_ref());
+        self.extracted = false;
+        self.mapped_opt = None;
+
+        if self.decompress {
+            if let Some(archive::Kind::Compressed(compression)) =
+                archive::Kind::from_path(&self.path)
+            {
+                self.content.clear();
+                archive::decompress(&self.path, compression, &mut self.content)?;
+                self.extracted = true;
+                self.lines.clear();
+                self.transcode_();
+                return Ok(());
+            }
+        }
 
         let mut f = std::fs::File::open(&self.path)?;
         let md = f.metadata()?;
+
+        let map = match self.mmap_mode {
+            MmapMode::Never => false,
+            MmapMode::Auto => md.len() >= MMAP_THRESHOLD,
+            //Empty files cannot be mapped
+            MmapMode::Always => md.len() > 0,
+        };
+        if map && md.is_file() {
+            //Mapping is only attempted for regular files, and falls back to reading when it fails.
+            //Safety: when the file is truncated by another process while we use it, we might get SIGBUS.
+            //This risk is accepted for search-only runs, which is why mmap is opt-in.
+            if let Ok(mapped) = unsafe { memmap2::Mmap::map(&f) } {
+                self.mapped_opt = Some(mapped);
+                self.content.clear();
+                self.lines.clear();
+                self.transcode_();
+                return Ok(());
+            }
+        }
+
         let md_size = md.len() as usize;
         self.content.reserve(md_size);
         self.content.clear();
         let act_size = f.read_to_end(&mut self.content)?;
-        assert_eq!(md_size, act_size);
+        if act_size != md_size {
+            return Err(Box::new(SizeChanged {
+                path: self.path.clone(),
+            }));
+        }
 
         self.lines.clear();
+        self.transcode_();
 
         Ok(())
     }
 
+    //Loads a member that was extracted from an archive, `path` is only used for reporting
+    pub fn load_member(&mut self, path: PathBuf, content: &[u8]) {
+        self.path = path;
+        self.extracted = true;
+        self.mapped_opt = None;
+        self.content.clear();
+        self.content.extend_from_slice(content);
+        self.lines.clear();
+        self.transcode_();
+    }
+
+    //The loaded content, either memory-mapped or read into a buffer
+    pub fn content(&self) -> &[u8] {
+        match &self.mapped_opt {
+            Some(mapped) => mapped,
+            None => &self.content,
+        }
+    }
+
+    fn transcode_(&mut self) {
+        let mut decoded = Content::new();
+        self.transcoding_opt = encoding::decode(self.content(), self.encoding_opt, &mut decoded);
+        if self.transcoding_opt.is_some() {
+            self.content = decoded;
+            self.mapped_opt = None;
+        }
+    }
+
     pub fn split_in_lines(&mut self) -> Result<()> {
-        let mut content = self.content.as_slice();
+        let mut content = match &self.mapped_opt {
+            Some(mapped) => &mapped[..],
+            None => self.content.as_slice(),
+        };
+        self.lines.clear();
         let mut start_ix = 0;
         let mut line_nr = 0;
         while !content.is_empty() {
             line_nr += 1;
 
-            let size = match content.iter().position(|&v| v == 0x0a_u8) {
-                None => content.len(),
-                Some(ix) => ix + 1,
-            };
+            let (size, eol_size) = line::next_record(content, self.separator);
 
-            self.lines.push(Line::new(line_nr, start_ix, size));
+            self.lines
+                .push(Line::new(line_nr, start_ix, size, eol_size));
             content = &content[size..];
             start_ix += size;
         }
+        self.lines_complete = true;
         Ok(())
     }
 
-    pub fn search_for_matches(&mut self) -> bool {
+    //Fills lines with the lines that match, together with their context.
+    //When possible, the complete content is searched first, and line boundaries are only computed around matches.
+    pub fn search_for_matches(&mut self) -> Result<bool> {
+        let search = match &self.search_opt {
+            None => return Ok(false),
+            Some(search) => search,
+        };
+
+        match &search.buffer_regex {
+            Some(buffer_regex) if self.separator == Separator::Byte(0x0a_u8) => {
+                let content = match &self.mapped_opt {
+                    Some(mapped) => &mapped[..],
+                    None => self.content.as_slice(),
+                };
+                self.lines.clear();
+                self.lines_complete = false;
+                let context = (self.context_before as usize, self.context_after as usize);
+                Ok(search_buffer_(
+                    content,
+                    search,
+                    buffer_regex,
+                    context,
+                    &mut self.lines,
+                ))
+            }
+            _ => {
+                self.split_in_lines()?;
+                Ok(self.search_lines_())
+            }
+        }
+    }
+
+    fn search_lines_(&mut self) -> bool {
         match &self.search_opt {
             None => false,
 
             Some(search) => {
-                let content = &self.content;
+                let content = match &self.mapped_opt {
+                    Some(mapped) => &mapped[..],
+                    None => self.content.as_slice(),
+                };
                 let mut found_match = false;
                 for line in self.lines.iter_mut() {
-                    found_match = line.search_for(&search, content) || found_match;
+                    found_match = line.search_for(search, content) || found_match;
                 }
                 found_match
             }
         }
     }
 
-    pub fn replace_and_write(&self) -> Result<()> {
+    //Makes sure lines contains all lines, not only the matching lines and their context
+    pub fn complete_lines(&mut self) -> Result<()> {
+        if !self.lines_complete {
+            self.split_in_lines()?;
+            self.search_lines_();
+        }
+        Ok(())
+    }
+
+    pub fn replace_and_write(&mut self) -> Result<()> {
+        //All lines are needed to write the complete content
+        self.complete_lines()?;
+
         match &self.replace_opt {
             None => panic!("Expected a replace string here"),
 
+            Some(_) if self.extracted => fail!(
+                "Replacement is not supported for '{}', it was decompressed or extracted from an archive",
+                self.path.display()
+            ),
+
+            Some(_) if self.transcoding_opt.is_some_and(|t| t.lossy) => fail!(
+                "Replacement is not supported for '{}', it could not be decoded without loss",
+                self.path.display()
+            ),
+
             Some(replace) => {
-                let mut f = std::fs::File::create(&self.path)?;
-                let content_slice = &self.content;
+                //The output is first composed in UTF-8 and encoded back into the original encoding
+                let mut output = Content::new();
+                let content_slice = self.content();
                 let search = self.search_opt.as_ref().unwrap();
                 for line in self.lines.iter() {
                     let line_slice = line.as_slice(content_slice);
                     let mut offset = 0;
                     for r in line.matches.iter() {
-                        f.write(&line_slice[offset..r.start])?;
+                        output.write_all(&line_slice[offset..r.start])?;
                         {
                             let match_bytes = &line_slice[r.start..r.end];
                             let caps = search.regex.captures(match_bytes);
@@ -106,7 +327,7 @@ impl Data {
                                     if caps.is_none() {
                                         fail!("Could not search for capture groups, but they are used here. This happens when a search with word boundary does not match in the substring match_str");
                                     }
-                                    f.write(
+                                    output.write_all(
                                         caps.as_ref()
                                             .unwrap()
                                             .get(*capture_ix as usize)
@@ -114,19 +335,127 @@ impl Data {
                                             .as_bytes(),
                                     )?;
                                 }
-                                f.write(part.as_bytes())?;
+                                output.write_all(part.as_bytes())?;
                             }
                         }
                         offset = r.end;
                     }
-                    f.write(&line_slice[offset..])?;
+                    //A missing line terminator at the end of the file stays missing
+                    let text_end = line_slice.len() - line.eol_size;
+                    output.write_all(&line_slice[offset..text_end])?;
+                    //Line endings are only normalized when records are lines
+                    let normalize = self.separator == Separator::Byte(0x0a_u8);
+                    match self.line_ending {
+                        _ if line.eol_size == 0 => {}
+                        _ if !normalize => output.write_all(&line_slice[text_end..])?,
+                        LineEnding::Preserve => output.write_all(&line_slice[text_end..])?,
+                        LineEnding::Lf => output.write_all(b"\n")?,
+                        LineEnding::CrLf => output.write_all(b"\r\n")?,
+                    }
+                }
+
+                let mut encoded = Content::new();
+                if let Some(transcoding) = &self.transcoding_opt {
+                    encoding::encode(&output, transcoding, &mut encoded)?;
+                    output = encoded;
                 }
+
+                //When self.path is a symlink, we edit its target and keep the symlink itself intact
+                let target = std::fs::canonicalize(&self.path)?;
+                std::fs::write(&target, &output)?;
             }
         }
         Ok(())
     }
 }
 
+//Searches `content` for candidate matches with `buffer_regex` and verifies each candidate line with `search`.
+//Only verified lines and their (before, after) context are added to `lines`, identical to how split_in_lines() would create them.
+fn search_buffer_(
+    content: &[u8],
+    search: &Search,
+    buffer_regex: &regex::bytes::Regex,
+    (before, after): (usize, usize),
+    lines: &mut Vec<Line>,
+) -> bool {
+    let is_newline = |&byte: &u8| byte == 0x0a_u8;
+    let mut found_match = false;
+    //Start and number of the first line that was not searched yet
+    let mut start = 0;
+    let mut nr = 1;
+    //End of the last line that was added, before context cannot go back further
+    let mut added_end = 0;
+    while start < content.len() {
+        let m = match buffer_regex.find_at(content, start) {
+            None => break,
+            Some(m) => m,
+        };
+        let line_start = match content[start..m.start()].iter().rposition(is_newline) {
+            None => start,
+            Some(ix) => start + ix + 1,
+        };
+        if line_start == content.len() {
+            //An empty match after the final line terminator
+            break;
+        }
+        nr += content[start..line_start]
+            .iter()
+            .filter(|b| is_newline(b))
+            .count() as u64;
+
+        let (size, eol_size) = line::next_record(&content[line_start..], Separator::Byte(0x0a_u8));
+        let mut line = Line::new(nr, line_start, size, eol_size);
+        start = line_start + size;
+        if !line.search_for(search, content) {
+            //The candidate match crosses a line boundary or depends on it
+            nr += 1;
+            continue;
+        }
+        found_match = true;
+
+        //Before context, none of these lines match
+        let first_ix = lines.len();
+        let mut end = line_start;
+        for before_nr in (nr.saturating_sub(before as u64).max(1)..nr).rev() {
+            if end <= added_end {
+                break;
+            }
+            let begin = match content[added_end..end - 1].iter().rposition(is_newline) {
+                None => added_end,
+                Some(ix) => added_end + ix + 1,
+            };
+            let size = end - begin;
+            lines.push(Line::new(
+                before_nr,
+                begin,
+                size,
+                line::eol_size(&content[begin..end]),
+            ));
+            end = begin;
+        }
+        lines[first_ix..].reverse();
+        lines.push(line);
+        nr += 1;
+
+        //After context, which is extended by each matching line
+        let mut after_left = after;
+        while after_left > 0 && start < content.len() {
+            let (size, eol_size) = line::next_record(&content[start..], Separator::Byte(0x0a_u8));
+            let mut line = Line::new(nr, start, size, eol_size);
+            if line.search_for(search, content) {
+                after_left = after;
+            } else {
+                after_left -= 1;
+            }
+            lines.push(line);
+            start += size;
+            nr += 1;
+        }
+        added_end = start;
+    }
+    found_match
+}
+
 #[test]
 pub fn test_file() -> Result<()> {
     use crate::search;
@@ -139,7 +468,7 @@ pub fn test_file() -> Result<()> {
     data.split_in_lines()?;
     println!("Found {} lines", data.lines.len());
 
-    assert!(data.search_for_matches());
+    assert!(data.search_for_matches()?);
 
     for line in &data.lines {
         if !line.matches.is_empty() {
@@ -149,3 +478,190 @@ pub fn test_file() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+pub fn test_load_mmap() -> Result<()> {
+    use crate::search;
+
+    let mut lines_per_mode = vec![];
+    for mmap_mode in [MmapMode::Never, MmapMode::Always].iter() {
+        let mut data = Data::new(search::Search::new("regex", false, false).ok(), false, None);
+        data.mmap_mode = *mmap_mode;
+        data.load(file!())?;
+        assert_eq!(data.mapped_opt.is_some(), *mmap_mode == MmapMode::Always);
+        data.split_in_lines()?;
+        assert!(data.search_for_matches()?);
+        let lines: Vec<_> = data
+            .lines
+            .iter()
+            .map(|line| (line.range.clone(), line.matches.clone()))
+            .collect();
+        lines_per_mode.push(lines);
+    }
+    assert_eq!(lines_per_mode[0], lines_per_mode[1]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_search_buffer() -> Result<()> {
+    use crate::search;
+
+    let content = b"a b\r\nfoo\n\nbar\r\nb\n\nend b";
+    for pattern in [
+        "b$",
+        "^$",
+        "^b",
+        "\\s",
+        "[^a-z]",
+        "b\\n",
+        "(?i-m)^foo",
+        "(?-R)b$",
+        "\\Aa",
+    ]
+    .iter()
+    {
+        for &(before, after) in [(0, 0), (1, 0), (0, 1), (2, 2)].iter() {
+            let mut data = Data::new(search::Search::new(pattern, false, true).ok(), false, None);
+            data.content = content.to_vec();
+            data.context_before = before;
+            data.context_after = after;
+
+            //Searching line per line results in all lines, we only keep matches and their context
+            let buffer_regex = data.search_opt.as_mut().unwrap().buffer_regex.take();
+            //Without buffer_regex, the second search is line per line as well and results in all lines
+            let keep_all = buffer_regex.is_none();
+            let found_match = data.search_for_matches()?;
+            let mut keep = vec![false; data.lines.len()];
+            for (ix, line) in data.lines.iter().enumerate() {
+                if !line.matches.is_empty() {
+                    let begin = ix.saturating_sub(before as usize);
+                    let end = (ix + after as usize + 1).min(keep.len());
+                    keep[begin..end].iter_mut().for_each(|k| *k = true);
+                }
+            }
+            let expected: Vec<_> = data
+                .lines
+                .iter()
+                .zip(keep.iter())
+                .filter(|(_, keep)| **keep || keep_all)
+                .map(|(line, _)| {
+                    (
+                        line.nr,
+                        line.range.clone(),
+                        line.eol_size,
+                        line.matches.clone(),
+                    )
+                })
+                .collect();
+
+            data.search_opt.as_mut().unwrap().buffer_regex = buffer_regex;
+            assert_eq!(data.search_for_matches()?, found_match);
+            let actual: Vec<_> = data
+                .lines
+                .iter()
+                .map(|line| {
+                    (
+                        line.nr,
+                        line.range.clone(),
+                        line.eol_size,
+                        line.matches.clone(),
+                    )
+                })
+                .collect();
+            assert_eq!(actual, expected, "{} {} {}", pattern, before, after);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+pub fn test_replace_via_symlink() -> Result<()> {
+    use crate::search;
+
+    let tmp = crate::util::TempDir::new("replace-symlink")?;
+    let root = &tmp.path;
+    let (target, link) = (root.join("target.txt"), root.join("link.txt"));
+    std::fs::write(&target, "needle\n")?;
+    std::os::unix::fs::symlink(&target, &link)?;
+
+    let mut data = Data::new(
+        search::Search::new("needle", false, false).ok(),
+        false,
+        Some(search::Replace::new("naald", &None)),
+    );
+    data.load(&link)?;
+    data.split_in_lines()?;
+    assert!(data.search_for_matches()?);
+    data.replace_and_write()?;
+
+    assert!(std::fs::symlink_metadata(&link)?.file_type().is_symlink());
+    assert_eq!(std::fs::read_to_string(&target)?, "naald\n");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_replace_utf16() -> Result<()> {
+    use crate::search;
+
+    let tmp = crate::util::TempDir::new("replace-utf16")?;
+    let root = &tmp.path;
+    let path = root.join("utf16.txt");
+    std::fs::write(&path, b"\xff\xfen\x00e\x00e\x00d\x00l\x00e\x00\n\x00")?;
+
+    let mut data = Data::new(
+        search::Search::new("needle", false, false).ok(),
+        false,
+        Some(search::Replace::new("naald", &None)),
+    );
+    data.load(&path)?;
+    assert_eq!(data.content, b"needle\n");
+    data.split_in_lines()?;
+    assert!(data.search_for_matches()?);
+    data.replace_and_write()?;
+
+    assert_eq!(
+        std::fs::read(&path)?,
+        b"\xff\xfen\x00a\x00a\x00l\x00d\x00\n\x00"
+    );
+
+    Ok(())
+}
+
+#[test]
+pub fn test_replace_line_endings() -> Result<()> {
+    use crate::search;
+
+    let tmp = crate::util::TempDir::new("line-endings")?;
+    let root = &tmp.path;
+    let path = root.join("crlf.txt");
+
+    let scns = [
+        (LineEnding::Preserve, &b"naald\r\nneedles\nnaald"[..]),
+        (LineEnding::Lf, b"naald\nneedles\nnaald"),
+        (LineEnding::CrLf, b"naald\r\nneedles\r\nnaald"),
+    ];
+    for (line_ending, expected) in scns.iter() {
+        std::fs::write(&path, b"needle\r\nneedles\nneedle")?;
+
+        let mut data = Data::new(
+            search::Search::new("needle$", false, false).ok(),
+            false,
+            Some(search::Replace::new("naald", &None)),
+        );
+        data.line_ending = *line_ending;
+        data.load(&path)?;
+        data.split_in_lines()?;
+        assert!(data.search_for_matches()?);
+        //Line 2 has no match and is not kept
+        assert!(data.lines.iter().all(|line| line.nr != 2));
+        data.replace_and_write()?;
+
+        assert_eq!(std::fs::read(&path)?, *expected);
+    }
+
+    Ok(())
+}
@@ -1,10 +1,15 @@
+use crate::archive;
 use crate::cli::{Options, OutputOnly};
 use crate::util::{MyError, Result};
-use ignore::WalkBuilder;
+use ignore::gitignore::{Gitignore, GitignoreBuilder, Glob};
+use ignore::{Match, WalkBuilder};
 use regex::bytes::{Regex, RegexBuilder};
 use std::collections::BTreeSet;
 use std::ffi::OsString;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+//Name of the gitignore-style file with rules that only apply to `mo`
+pub const MO_IGNORE_FILENAME: &str = ".moignore";
 
 pub struct Scanner<'a> {
     root: std::path::PathBuf,
@@ -16,6 +21,48 @@ pub struct Scanner<'a> {
 
 pub type Paths = Vec<std::path::PathBuf>;
 
+//Explains why a path is not produced by Scanner::scan().
+//`path` is the entry that was skipped, this is either the requested path or one of its parent folders.
+#[derive(Debug)]
+pub struct IgnoreReason {
+    pub path: PathBuf,
+    pub source: String,
+    pub rule: Option<String>,
+}
+
+impl IgnoreReason {
+    fn new(path: &Path, source: &str, rule: Option<&str>) -> IgnoreReason {
+        IgnoreReason {
+            path: path.to_path_buf(),
+            source: source.to_string(),
+            rule: rule.map(|r| r.to_string()),
+        }
+    }
+
+    fn from_glob(path: &Path, glob: &Glob) -> IgnoreReason {
+        let source = match glob.from() {
+            None => "unknown ignore file".to_string(),
+            Some(from) => from.display().to_string(),
+        };
+        IgnoreReason::new(path, &source, Some(glob.original()))
+    }
+}
+
+impl std::fmt::Display for IgnoreReason {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.rule {
+            None => write!(fmt, "'{}' is skipped: {}", self.path.display(), self.source),
+            Some(rule) => write!(
+                fmt,
+                "'{}' is ignored by rule '{}' from '{}'",
+                self.path.display(),
+                rule,
+                self.source
+            ),
+        }
+    }
+}
+
 impl Scanner<'_> {
     pub fn new<'a, P>(root: P, options: &'a Options) -> Result<Scanner<'a>>
     where
@@ -59,19 +106,39 @@ impl Scanner<'_> {
     where
         P: AsRef<Path>,
     {
-        let walk = WalkBuilder::new(parent)
+        let mut builder = WalkBuilder::new(parent);
+        builder
             .hidden(!self.options.search_hidden_files)
             .ignore(!self.options.search_ignored_files)
             .git_ignore(!self.options.search_ignored_files)
             .git_exclude(!self.options.search_ignored_files)
             .git_global(!self.options.search_ignored_files)
-            .build();
+            .follow_links(self.options.follow_links)
+            .same_file_system(self.options.same_file_system);
+        if !self.options.search_ignored_files {
+            builder.add_custom_ignore_filename(MO_IGNORE_FILENAME);
+            for ignore_file in self.options.ignore_file_vec.iter() {
+                if let Some(err) = builder.add_ignore(ignore_file) {
+                    fail!("Could not load ignore file '{}': {}", ignore_file, err);
+                }
+            }
+        }
+        let walk = builder.build();
+
+        //When following symlinks, the same file or folder can be reached via different paths.
+        //We only keep the first one to make sure a replacement is not applied twice.
+        let mut seen_targets = BTreeSet::<PathBuf>::new();
 
         for entry in walk {
             match entry {
                 Err(err) => {
+                    //Warnings go to stderr to keep the list of paths on stdout intact
                     if self.options.verbose_level >= 1 {
-                        println!("Warning: could not walk this entry: {:?}", err);
+                        if is_loop_(&err) {
+                            eprintln!("Warning: skipping symlink loop: {}", err);
+                        } else {
+                            eprintln!("Warning: could not walk this entry: {:?}", err);
+                        }
                     }
                 }
                 Ok(entry) => {
@@ -80,25 +147,29 @@ impl Scanner<'_> {
                         Some(ft) => ft,
                     };
                     let path = entry.into_path();
+                    //The index is never searched, also not when hidden files are included
+                    if is_index_(&path) {
+                        continue;
+                    }
 
                     let do_add_path = match self.options.output_only {
                         None | Some(OutputOnly::Match) => {
-                            true && file_type.is_file()
-                                && self.extension_ok_(&path)
-                                && self.name_ok_(&path)
+                            file_type.is_file() && self.file_ok(&path)
                         }
 
-                        Some(OutputOnly::Filenames) => {
-                            true && file_type.is_file()
-                                && self.extension_ok_(&path)
-                                && self.name_ok_(&path)
-                        }
+                        Some(OutputOnly::Filenames) => file_type.is_file() && self.file_ok(&path),
 
-                        Some(OutputOnly::Folders) => {
-                            true && file_type.is_dir() && self.name_ok_(&path)
-                        }
+                        Some(OutputOnly::Folders) => file_type.is_dir() && self.name_ok_(&path),
                     };
 
+                    if do_add_path && self.options.follow_links {
+                        if let Ok(target) = std::fs::canonicalize(&path) {
+                            if !seen_targets.insert(target) {
+                                continue;
+                            }
+                        }
+                    }
+
                     if do_add_path {
                         paths.push(path.to_path_buf());
                     }
@@ -108,7 +179,151 @@ impl Scanner<'_> {
         Ok(())
     }
 
+    //Returns the reason why `path` would be skipped by scan(), or None when it would be produced
+    pub fn why_ignored<P>(&self, path: P) -> Result<Option<IgnoreReason>>
+    where
+        P: AsRef<Path>,
+    {
+        let root = std::fs::canonicalize(&self.root)?;
+        let path = std::fs::canonicalize(path.as_ref())?;
+        let rel = match path.strip_prefix(&root) {
+            Err(_) => fail!(
+                "'{}' is not located below root '{}'",
+                path.display(),
+                root.display()
+            ),
+            Ok(rel) => rel.to_path_buf(),
+        };
+
+        //The walker does not descend into skipped folders, we check all entries top-down
+        let mut entry = root.clone();
+        let mut walk_entry = self.root.clone();
+        for component in rel.components() {
+            entry.push(component);
+            walk_entry.push(component);
+            let is_dir = entry.is_dir();
+
+            //Like the walker, a whitelist rule also makes a hidden entry visible
+            let m = if self.options.search_ignored_files {
+                Match::None
+            } else {
+                self.ignore_match_(&entry, &walk_entry, is_dir)
+            };
+            match m {
+                Match::Ignore(reason) => return Ok(Some(reason)),
+                Match::Whitelist(_) => {}
+                Match::None => {
+                    if !self.options.search_hidden_files && is_hidden_(&entry) {
+                        return Ok(Some(IgnoreReason::new(&entry, "hidden entry", None)));
+                    }
+                }
+            }
+        }
+
+        let walk_path = self.root.join(&rel);
+        let reason = match self.options.output_only {
+            Some(OutputOnly::Folders) => {
+                if !path.is_dir() {
+                    Some("not a folder")
+                } else if !self.name_ok_(&walk_path) {
+                    Some("filepath pattern")
+                } else {
+                    None
+                }
+            }
+            _ => {
+                if !path.is_file() {
+                    Some("not a file")
+                } else if !self.extension_ok_(&walk_path) {
+                    Some("extension or binary filter")
+                } else if !self.name_ok_(&walk_path) {
+                    Some("filepath pattern")
+                } else {
+                    None
+                }
+            }
+        };
+        Ok(reason.map(|source| IgnoreReason::new(&path, source, None)))
+    }
+
+    //Follows the precedence of the walker from the ignore crate: .moignore, .ignore, .gitignore,
+    //.git/info/exclude, global git excludes and explicit ignore files, in that order.
+    //Within each source, the deepest folder with a matching rule decides, also for whitelist rules.
+    fn ignore_match_(&self, entry: &Path, walk_entry: &Path, is_dir: bool) -> Match<IgnoreReason> {
+        let folders: Vec<&Path> = match entry.parent() {
+            None => vec![],
+            Some(parent) => parent.ancestors().collect(),
+        };
+        //Git rules only apply up to and including the root of the repository
+        let git_folders = match folders.iter().position(|f| f.join(".git").exists()) {
+            None => &folders[0..0],
+            Some(ix) => &folders[..=ix],
+        };
+
+        let first_match = |gitignores: Vec<Gitignore>, path: &Path| -> Match<IgnoreReason> {
+            gitignores
+                .iter()
+                .map(|gitignore| {
+                    gitignore
+                        .matched(path, is_dir)
+                        .map(|glob| IgnoreReason::from_glob(entry, glob))
+                })
+                .find(|m| !m.is_none())
+                .unwrap_or(Match::None)
+        };
+        let per_folder = |folders: &[&Path], filename: &str| -> Vec<Gitignore> {
+            folders
+                .iter()
+                .filter_map(|folder| load_gitignore_(folder, &folder.join(filename)))
+                .collect()
+        };
+
+        let m_custom = first_match(per_folder(&folders, MO_IGNORE_FILENAME), entry);
+        let m_ignore = first_match(per_folder(&folders, ".ignore"), entry);
+        let m_gi = first_match(per_folder(git_folders, ".gitignore"), entry);
+        let m_gi_exclude = first_match(per_folder(git_folders, ".git/info/exclude"), entry);
+        let m_global = if git_folders.is_empty() {
+            Match::None
+        } else {
+            first_match(vec![Gitignore::global().0], walk_entry)
+        };
+        let explicit = self
+            .options
+            .ignore_file_vec
+            .iter()
+            .rev()
+            .filter_map(|file| load_gitignore_(Path::new(""), Path::new(file)))
+            .collect();
+        let m_explicit = first_match(explicit, walk_entry);
+
+        m_custom
+            .or(m_ignore)
+            .or(m_gi)
+            .or(m_gi_exclude)
+            .or(m_global)
+            .or(m_explicit)
+    }
+
+    //Checks if a file passes the extension and filepath filters
+    pub fn file_ok(&self, path: &std::path::Path) -> bool {
+        self.extension_ok_(path) && self.name_ok_(path)
+    }
+
     fn extension_ok_(&self, path: &std::path::Path) -> bool {
+        if self.options.search_compressed {
+            match archive::Kind::from_path(path) {
+                None => {}
+                //Archive members are filtered individually
+                Some(kind) if kind.is_archive() => return true,
+                //Compressed files are filtered against the extension of their content
+                Some(_) => {
+                    if let Some(stem) = path.file_stem() {
+                        return self.extension_ok_(Path::new(stem));
+                    }
+                }
+            }
+        }
+
         //Filter against allowed extensions, if any
         if let Some(extension) = path.extension() {
             let is_binary = self.binary_extensions.contains(extension);
@@ -161,6 +376,42 @@ impl Scanner<'_> {
     }
 }
 
+fn is_loop_(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. } => is_loop_(err),
+        ignore::Error::WithDepth { err, .. } => is_loop_(err),
+        ignore::Error::WithLineNumber { err, .. } => is_loop_(err),
+        _ => false,
+    }
+}
+
+fn is_index_(path: &Path) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        None => false,
+        Some(name) => {
+            name == crate::index::INDEX_FILENAME || name == crate::index::INDEX_TMP_FILENAME
+        }
+    }
+}
+
+fn is_hidden_(path: &Path) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        None => false,
+        Some(name) => name.starts_with('.'),
+    }
+}
+
+fn load_gitignore_(root: &Path, filepath: &Path) -> Option<Gitignore> {
+    if !filepath.is_file() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(root);
+    //Invalid rules are skipped, just like the walker does
+    let _ = builder.add(filepath);
+    builder.build().ok()
+}
+
 fn all_binary_extensions_() -> BTreeSet<OsString> {
     let mut set = BTreeSet::<OsString>::new();
     for ext in &[
@@ -177,7 +428,130 @@ fn test_scan_folder() -> Result<()> {
     let options = Options::new();
     let scanner = Scanner::new(".", &options)?;
     let paths = scanner.scan()?;
-    assert!(paths.len() > 0);
+    assert!(!paths.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_why_ignored() -> Result<()> {
+    let tmp = crate::util::TempDir::new("why-ignored")?;
+    let root = tmp.path.clone();
+    std::fs::create_dir_all(root.join("sub"))?;
+    std::fs::write(root.join(MO_IGNORE_FILENAME), "*.log\n")?;
+    std::fs::write(root.join("sub").join("a.log"), "")?;
+    std::fs::write(root.join("sub").join("a.txt"), "")?;
+
+    let options = Options::new();
+    let scanner = Scanner::new(&root, &options)?;
+    let paths = scanner.scan()?;
+    assert!(paths.iter().any(|p| p.ends_with("a.txt")));
+    assert!(!paths.iter().any(|p| p.ends_with("a.log")));
+
+    let reason = scanner
+        .why_ignored(root.join("sub").join("a.log"))?
+        .unwrap();
+    assert_eq!(reason.rule, Some("*.log".to_string()));
+    assert!(reason.source.ends_with(MO_IGNORE_FILENAME));
+    assert!(scanner
+        .why_ignored(root.join("sub").join("a.txt"))?
+        .is_none());
+
+    let options = Options {
+        search_ignored_files: true,
+        ..Options::default()
+    };
+    let scanner = Scanner::new(&root, &options)?;
+    assert!(scanner
+        .why_ignored(root.join("sub").join("a.log"))?
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_why_ignored_matches_scan() -> Result<()> {
+    let tmp = crate::util::TempDir::new("why-ignored-scan")?;
+    let root = tmp.path.clone();
+    let files = [
+        (".git/HEAD", ""),
+        (".gitignore", "*.log\nbuild/\n"),
+        (".ignore", "!.config\n"),
+        (MO_IGNORE_FILENAME, "*.tmp\n!keep.tmp\n"),
+        ("extra.ignore", "*.bak\n"),
+        ("a.txt", ""),
+        ("a.log", ""),
+        ("a.tmp", ""),
+        ("a.bak", ""),
+        ("keep.tmp", ""),
+        (".hidden.txt", ""),
+        (".config/b.txt", ""),
+        ("build/c.txt", ""),
+        ("sub/.gitignore", "!d.log\n"),
+        ("sub/d.log", ""),
+        ("sub/e.log", ""),
+        ("sub/.moignore", "*.txt\n"),
+        ("sub/f.txt", ""),
+        ("sub/deep/.moignore", "!g.txt\n"),
+        ("sub/deep/g.txt", ""),
+        //.moignore rules take precedence over .ignore rules, also when these are deeper
+        ("sub/deep/.ignore", "!h.txt\n"),
+        ("sub/deep/h.txt", ""),
+    ];
+    for (file, content) in files.iter() {
+        let path = root.join(file);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, content)?;
+    }
+
+    let options = Options {
+        ignore_file_vec: vec![root.join("extra.ignore").display().to_string()],
+        ..Options::default()
+    };
+    let scanner = Scanner::new(&root, &options)?;
+    let paths = scanner.scan()?;
+    assert!(paths.iter().any(|p| p.ends_with(".config/b.txt")));
+    assert!(paths.iter().any(|p| p.ends_with("sub/d.log")));
+    assert!(paths.iter().any(|p| p.ends_with("sub/deep/g.txt")));
+
+    //Each file is either produced by scan(), or why_ignored() explains why not
+    for (file, _) in files.iter() {
+        let path = root.join(file);
+        let produced = paths.contains(&path);
+        let reason = scanner.why_ignored(&path)?;
+        assert_eq!(produced, reason.is_none(), "{}: {:?}", file, reason);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scan_symlinks() -> Result<()> {
+    let tmp = crate::util::TempDir::new("symlinks")?;
+    let root = tmp.path.clone();
+    std::fs::create_dir_all(root.join("real"))?;
+    std::fs::write(root.join("real").join("a.txt"), "")?;
+    std::os::unix::fs::symlink(root.join("real"), root.join("link"))?;
+    std::os::unix::fs::symlink(&root, root.join("real").join("loop"))?;
+
+    let options = Options::new();
+    let paths = Scanner::new(&root, &options)?.scan()?;
+    assert_eq!(paths.len(), 1);
+
+    //Following links finds the same file twice, the duplicate and the loop are skipped
+    let options = Options {
+        follow_links: true,
+        ..Options::default()
+    };
+    let paths = Scanner::new(&root, &options)?.scan()?;
+    assert_eq!(paths.len(), 1);
+
+    std::fs::remove_file(root.join("real").join("loop"))?;
+    std::fs::write(root.join("b.txt"), "")?;
+    std::os::unix::fs::symlink(root.join("b.txt"), root.join("real").join("b-link.txt"))?;
+    let paths = Scanner::new(root.join("real"), &options)?.scan()?;
+    assert!(paths.iter().any(|p| p.ends_with("b-link.txt")));
 
     Ok(())
 }
@@ -0,0 +1,169 @@
+use crate::template::{self, Part};
+use crate::util::{MyError, Result};
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+enum Field {
+    //Absolute path, percent-encoded
+    Path,
+    Line,
+    Col,
+    Host,
+}
+
+//Turns printed paths into OSC 8 terminal hyperlinks, using a URL template
+#[derive(Debug, PartialEq)]
+pub struct Hyperlink {
+    parts: Vec<Part<Field>>,
+    host: String,
+    //The last path and its encoding, consecutive lines of the same file are only canonicalized once
+    encoded_path: RefCell<Option<(PathBuf, String)>>,
+}
+
+impl Hyperlink {
+    //`format` is a preset (file or vscode) or a URL template with {path}, {line}, {col} and {host}
+    pub fn new(format: &str) -> Result<Hyperlink> {
+        let url_template = match format {
+            "file" => "file://{host}{path}",
+            "vscode" => "vscode://file{path}:{line}:{col}",
+            _ => format,
+        };
+        let parts = template::parse(url_template, |name| {
+            let field = match name {
+                "path" => Field::Path,
+                "line" => Field::Line,
+                "col" => Field::Col,
+                "host" => Field::Host,
+                _ => fail!("Unknown hyperlink field '{{{}}}'", name),
+            };
+            Ok(field)
+        })?;
+        Ok(Hyperlink {
+            parts,
+            host: hostname_(),
+            encoded_path: RefCell::new(None),
+        })
+    }
+
+    //Without a known location, the link refers to line 1, column 1
+    pub fn url(&self, path: &Path, line_opt: Option<u64>, col_opt: Option<usize>) -> String {
+        let mut url = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => url.push_str(text),
+                Part::Field(field) => match field {
+                    Field::Path => url.push_str(&self.encoded_path_(path)),
+                    Field::Line => url.push_str(&line_opt.unwrap_or(1).to_string()),
+                    Field::Col => url.push_str(&col_opt.unwrap_or(1).to_string()),
+                    Field::Host => url.push_str(&self.host),
+                },
+            }
+        }
+        url
+    }
+
+    pub fn wrap(&self, text: &str, url: &str) -> String {
+        format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+    }
+
+    fn encoded_path_(&self, path: &Path) -> String {
+        let mut encoded_path = self.encoded_path.borrow_mut();
+        if let Some((prev_path, encoded)) = &*encoded_path {
+            if prev_path == path {
+                return encoded.clone();
+            }
+        }
+        let mut encoded = String::new();
+        encode_path_(path, &mut encoded);
+        *encoded_path = Some((path.to_path_buf(), encoded.clone()));
+        encoded
+    }
+}
+
+//The host is only used for file URLs, it is left empty when unknown
+fn hostname_() -> String {
+    system_hostname_()
+        .filter(|host| !host.is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn system_hostname_() -> Option<String> {
+    let mut buffer = [0_u8; 256];
+    //Safety: gethostname() writes at most buffer.len() bytes
+    let ret = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if ret != 0 {
+        return None;
+    }
+    let size = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Some(String::from_utf8_lossy(&buffer[..size]).into_owned())
+}
+#[cfg(not(unix))]
+fn system_hostname_() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+//Appends the absolute path, percent-encoding everything except unreserved characters and '/'
+fn encode_path_(path: &Path, url: &mut String) {
+    //Paths that do not exist, eg, archive members, are taken relative to the working folder
+    let abs_path = std::fs::canonicalize(path).unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    });
+    for &byte in abs_path.to_string_lossy().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                url.push(byte as char)
+            }
+            _ => {
+                let _ = write!(url, "%{:02X}", byte);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_hyperlink() -> Result<()> {
+    let path = Path::new("/tmp/mo test/f.rs");
+
+    let hyperlink = Hyperlink::new("vscode")?;
+    assert_eq!(
+        hyperlink.url(path, Some(12), Some(3)),
+        "vscode://file/tmp/mo%20test/f.rs:12:3"
+    );
+    assert_eq!(
+        hyperlink.url(path, None, None),
+        "vscode://file/tmp/mo%20test/f.rs:1:1"
+    );
+
+    let hyperlink = Hyperlink::new("myeditor://open?file={path}&line={line}")?;
+    assert_eq!(
+        hyperlink.url(path, Some(12), None),
+        "myeditor://open?file=/tmp/mo%20test/f.rs&line=12"
+    );
+    assert_eq!(
+        hyperlink.wrap("f.rs", "file:///f.rs"),
+        "\x1b]8;;file:///f.rs\x1b\\f.rs\x1b]8;;\x1b\\"
+    );
+
+    //A different path is not taken from the previous encoding
+    let hyperlink = Hyperlink::new("file")?;
+    let host = hostname_();
+    assert!(!host.is_empty());
+    assert_eq!(
+        hyperlink.url(path, None, None),
+        format!("file://{}/tmp/mo%20test/f.rs", host)
+    );
+    assert_eq!(
+        hyperlink.url(Path::new("/tmp/mo test/g.rs"), None, None),
+        format!("file://{}/tmp/mo%20test/g.rs", host)
+    );
+
+    assert!(Hyperlink::new("file://{file}").is_err());
+
+    Ok(())
+}
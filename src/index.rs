@@ -0,0 +1,668 @@
+use crate::cli::{IndexCommand, Options};
+use crate::file;
+use crate::folder::Scanner;
+use crate::util::{MyError, Result};
+use regex_syntax::hir::{Class, Hir, HirKind};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+//Name of the index file, located in the root folder that it indexes
+pub const INDEX_FILENAME: &str = ".moindex";
+//The index is written to this file first and then renamed to INDEX_FILENAME
+pub const INDEX_TMP_FILENAME: &str = ".moindex.tmp";
+
+//Layout of the index file: magic, postings, files, trigram table and footer.
+//Postings are the delta-encoded varint ids of the files that contain a trigram.
+//Table entries have a fixed size, allowing a binary search without reading the whole index.
+const MAGIC: &[u8] = b"MOINDEX1";
+const ENTRY_SIZE: usize = 16;
+const FOOTER_SIZE: usize = 32;
+
+type Trigram = u32;
+type FileId = u32;
+
+//Files with a different mtime or size than when they were indexed are always searched
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Stamp {
+    mtime: u64,
+    size: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Stamp> {
+        let md = std::fs::metadata(path).ok()?;
+        let mtime = md
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        Some(Stamp {
+            mtime: mtime.as_nanos() as u64,
+            size: md.len(),
+        })
+    }
+}
+
+//Indexed file, `path` is relative to the root
+#[derive(Clone)]
+struct Entry {
+    path: PathBuf,
+    stamp: Stamp,
+}
+
+struct Index {
+    mapped: memmap2::Mmap,
+    files: Vec<Entry>,
+    table: std::ops::Range<usize>,
+}
+
+impl Index {
+    //Returns None when `root` has no index
+    fn open(root: &Path) -> Result<Option<Index>> {
+        let path = root.join(INDEX_FILENAME);
+        let file = match std::fs::File::open(&path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => fail!("Could not open index '{}': {}", path.display(), err),
+            Ok(file) => file,
+        };
+        let len = match file.metadata() {
+            Err(err) => fail!("Could not read index '{}': {}", path.display(), err),
+            Ok(metadata) => metadata.len(),
+        };
+        //An empty file cannot be mapped, and is as corrupt as a truncated one
+        let mapped_opt = if len < (MAGIC.len() + FOOTER_SIZE) as u64 {
+            None
+        } else {
+            //The index is replaced via a rename when it is updated, it is never modified in place
+            match unsafe { memmap2::Mmap::map(&file) } {
+                Err(err) => fail!("Could not read index '{}': {}", path.display(), err),
+                Ok(mapped) => Some(mapped),
+            }
+        };
+        match mapped_opt.and_then(Index::parse_) {
+            None => fail!(
+                "Index '{}' is corrupt, use `--index build` to recreate it",
+                path.display()
+            ),
+            Some(index) => Ok(Some(index)),
+        }
+    }
+
+    fn parse_(mapped: memmap2::Mmap) -> Option<Index> {
+        let len = mapped.len();
+        if len < MAGIC.len() + FOOTER_SIZE || !mapped.starts_with(MAGIC) {
+            return None;
+        }
+        let footer = &mapped[len - FOOTER_SIZE..];
+        let files_offset = read_u64_(footer, 0) as usize;
+        let file_count = read_u64_(footer, 8) as usize;
+        let table_offset = read_u64_(footer, 16) as usize;
+        let table_end = table_offset.checked_add(read_u64_(footer, 24) as usize * ENTRY_SIZE)?;
+        if files_offset < MAGIC.len()
+            || files_offset > table_offset
+            || table_end != len - FOOTER_SIZE
+        {
+            return None;
+        }
+
+        let mut files = Vec::with_capacity(file_count);
+        let mut varints = Varints(&mapped[files_offset..table_offset]);
+        for _ in 0..file_count {
+            let path_size = varints.next()? as usize;
+            let path = std::str::from_utf8(varints.take(path_size)?).ok()?;
+            files.push(Entry {
+                path: PathBuf::from(path),
+                stamp: Stamp {
+                    mtime: varints.next()?,
+                    size: varints.next()?,
+                },
+            });
+        }
+        Some(Index {
+            mapped,
+            files,
+            table: table_offset..table_end,
+        })
+    }
+
+    fn trigram_count(&self) -> usize {
+        self.table.len() / ENTRY_SIZE
+    }
+
+    //Trigram of table entry `ix`, with the number of files that contain it and the offset of their postings
+    fn entry_(&self, ix: usize) -> (Trigram, usize, usize) {
+        let entry = &self.mapped[self.table.start + ix * ENTRY_SIZE..];
+        (
+            read_u32_(entry, 0),
+            read_u32_(entry, 4) as usize,
+            read_u64_(entry, 8) as usize,
+        )
+    }
+
+    fn find_(&self, trigram: Trigram) -> Option<usize> {
+        let (mut begin, mut end) = (0, self.trigram_count());
+        while begin < end {
+            let mid = (begin + end) / 2;
+            match self.entry_(mid).0.cmp(&trigram) {
+                std::cmp::Ordering::Less => begin = mid + 1,
+                std::cmp::Ordering::Greater => end = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    fn postings_(&self, ix: usize) -> Vec<FileId> {
+        let (_, count, offset) = self.entry_(ix);
+        let mut varints = Varints(self.mapped.get(offset..).unwrap_or_default());
+        let mut id = 0;
+        (0..count)
+            .map_while(|_| {
+                id += varints.next()?;
+                Some(id as FileId)
+            })
+            .collect()
+    }
+
+    //Indicates for each file if it contains all `trigrams`
+    fn candidates_(&self, trigrams: &[Trigram]) -> Vec<bool> {
+        let mut is_candidate = vec![true; self.files.len()];
+        for &trigram in trigrams {
+            let mut contains = vec![false; self.files.len()];
+            if let Some(ix) = self.find_(trigram) {
+                for id in self.postings_(ix) {
+                    if let Some(contains) = contains.get_mut(id as usize) {
+                        *contains = true;
+                    }
+                }
+            }
+            for (is_candidate, contains) in is_candidate.iter_mut().zip(contains) {
+                *is_candidate &= contains;
+            }
+        }
+        is_candidate
+    }
+}
+
+//Narrows down the files of a root folder that can match the search pattern, using its index.
+//Files that are not indexed or that changed since the index was updated are always kept.
+pub struct Filter {
+    root: PathBuf,
+    files: HashMap<PathBuf, (Stamp, bool)>,
+}
+
+impl Filter {
+    //Returns None when the index cannot help, eg, when there is no index
+    //or when the pattern does not contain a literal of at least 3 bytes
+    pub fn new(root: &Path, options: &Options, file_data: &file::Data) -> Result<Option<Filter>> {
+        let search = match &file_data.search_opt {
+            None => return Ok(None),
+            Some(search) => search,
+        };
+        //Inverted searches need all files, and decoded or decompressed content is not what was indexed
+        if options.invert_pattern || options.search_compressed || options.encoding_opt.is_some() {
+            return Ok(None);
+        }
+        let trigrams = query_trigrams_(search.regex.as_str(), options.case_sensitive);
+        if trigrams.is_empty() {
+            return Ok(None);
+        }
+        let index = match Index::open(root)? {
+            None => return Ok(None),
+            Some(index) => index,
+        };
+
+        let is_candidate = index.candidates_(&trigrams);
+        let files = index
+            .files
+            .into_iter()
+            .zip(is_candidate)
+            .map(|(entry, is_candidate)| (entry.path, (entry.stamp, is_candidate)))
+            .collect();
+        Ok(Some(Filter {
+            root: root.to_path_buf(),
+            files,
+        }))
+    }
+
+    pub fn keep(&self, path: &Path) -> bool {
+        let rel = path.strip_prefix(&self.root).unwrap_or(path);
+        match self.files.get(rel) {
+            None => true,
+            Some((stamp, is_candidate)) => *is_candidate || Stamp::of(path) != Some(*stamp),
+        }
+    }
+}
+
+pub fn run(command: IndexCommand, root: &Path, options: &Options) -> Result<()> {
+    let path = root.join(INDEX_FILENAME);
+    match command {
+        IndexCommand::Build | IndexCommand::Update => {
+            let (file_count, read_count) = update_(root, options, command == IndexCommand::Build)?;
+            println!(
+                "Indexed {} files in '{}', {} files were read",
+                file_count,
+                path.display(),
+                read_count
+            );
+        }
+        IndexCommand::Info => match Index::open(root)? {
+            None => println!("'{}' has no index", root.display()),
+            Some(index) => {
+                let mut stamps: HashMap<&Path, Stamp> = index
+                    .files
+                    .iter()
+                    .map(|entry| (entry.path.as_path(), entry.stamp))
+                    .collect();
+                let paths = Scanner::new(root, options)?.scan()?;
+                let mut changed_count = 0;
+                for path in paths.iter() {
+                    let rel = path.strip_prefix(root).unwrap_or(path);
+                    if stamps.remove(rel) != Stamp::of(path) {
+                        changed_count += 1;
+                    }
+                }
+                println!("Index '{}'", path.display());
+                println!("  Files: {}", index.files.len());
+                println!("  Trigrams: {}", index.trigram_count());
+                println!("  Size: {} bytes", index.mapped.len());
+                println!(
+                    "  Changed since last update: {} files, {} removed",
+                    changed_count,
+                    stamps.len()
+                );
+            }
+        },
+        IndexCommand::Drop => match std::fs::remove_file(&path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                println!("'{}' has no index", root.display())
+            }
+            Err(err) => fail!("Could not remove index '{}': {}", path.display(), err),
+            Ok(()) => println!("Removed index '{}'", path.display()),
+        },
+    }
+    Ok(())
+}
+
+//Writes the index for the files that are found in `root`. Unless `rebuild` is set,
+//the existing index is reused for files that did not change. Returns the number of indexed and read files.
+fn update_(root: &Path, options: &Options, rebuild: bool) -> Result<(usize, usize)> {
+    let old_opt = if rebuild { None } else { Index::open(root)? };
+    let old_files = old_opt.as_ref().map_or(&[][..], |old| &old.files[..]);
+    let old_ids: HashMap<&Path, usize> = old_files
+        .iter()
+        .enumerate()
+        .map(|(ix, entry)| (entry.path.as_path(), ix))
+        .collect();
+
+    //Kept files get the first ids, in their original order, their postings remain sorted
+    let mut is_kept = vec![false; old_files.len()];
+    let mut fresh = vec![];
+    for path in Scanner::new(root, options)?.scan()? {
+        let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        let stamp = match Stamp::of(&path) {
+            Some(stamp) if rel.to_str().is_some() => stamp,
+            //Files that cannot be indexed are always searched
+            _ => continue,
+        };
+        //Large files are searched while they are read, we do not load them either
+        if stamp.size > options.stream_threshold {
+            continue;
+        }
+        match old_ids.get(rel.as_path()) {
+            Some(&ix) if old_files[ix].stamp == stamp => is_kept[ix] = true,
+            _ => fresh.push((path, Entry { path: rel, stamp })),
+        }
+    }
+    let mut files = vec![];
+    let mut remap = vec![None; old_files.len()];
+    for (ix, entry) in old_files.iter().enumerate() {
+        if is_kept[ix] {
+            remap[ix] = Some(files.len() as FileId);
+            files.push(entry.clone());
+        }
+    }
+
+    let mut fresh_postings = BTreeMap::<Trigram, Vec<u8>>::new();
+    let mut file_data = file::Data::new(None, false, None);
+    let mut read_count = 0;
+    for (path, entry) in fresh {
+        if file_data.load(&path).is_err() {
+            continue;
+        }
+        read_count += 1;
+        let id = files.len() as FileId;
+        for trigram in content_trigrams_(file_data.content()) {
+            write_varint_(fresh_postings.entry(trigram).or_default(), id as u64);
+        }
+        files.push(entry);
+    }
+
+    let index_path = root.join(INDEX_FILENAME);
+    let tmp_path = root.join(INDEX_TMP_FILENAME);
+    let file = match std::fs::File::create(&tmp_path) {
+        Err(err) => fail!("Could not create index '{}': {}", tmp_path.display(), err),
+        Ok(file) => file,
+    };
+    let mut writer = std::io::BufWriter::new(file);
+    writer.write_all(MAGIC)?;
+    let mut offset = MAGIC.len();
+
+    let mut trigrams: Vec<Trigram> = fresh_postings.keys().cloned().collect();
+    if let Some(old) = &old_opt {
+        trigrams.extend((0..old.trigram_count()).map(|ix| old.entry_(ix).0));
+    }
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    let mut table = vec![];
+    let mut bytes = vec![];
+    for trigram in trigrams {
+        let mut ids = vec![];
+        if let Some(old) = &old_opt {
+            if let Some(ix) = old.find_(trigram) {
+                ids.extend(
+                    old.postings_(ix)
+                        .iter()
+                        .filter_map(|&id| remap.get(id as usize).cloned().flatten()),
+                );
+            }
+        }
+        if let Some(postings) = fresh_postings.get(&trigram) {
+            let mut varints = Varints(postings);
+            while let Some(id) = varints.next() {
+                ids.push(id as FileId);
+            }
+        }
+        if ids.is_empty() {
+            continue;
+        }
+        bytes.clear();
+        let mut prev = 0;
+        for &id in ids.iter() {
+            write_varint_(&mut bytes, (id - prev) as u64);
+            prev = id;
+        }
+        writer.write_all(&bytes)?;
+        table.push((trigram, ids.len() as u32, offset as u64));
+        offset += bytes.len();
+    }
+
+    let files_offset = offset;
+    for entry in files.iter() {
+        bytes.clear();
+        let path = entry.path.to_str().unwrap_or_default().as_bytes();
+        write_varint_(&mut bytes, path.len() as u64);
+        bytes.extend_from_slice(path);
+        write_varint_(&mut bytes, entry.stamp.mtime);
+        write_varint_(&mut bytes, entry.stamp.size);
+        writer.write_all(&bytes)?;
+        offset += bytes.len();
+    }
+    for (trigram, count, postings_offset) in table.iter() {
+        writer.write_all(&trigram.to_le_bytes())?;
+        writer.write_all(&count.to_le_bytes())?;
+        writer.write_all(&postings_offset.to_le_bytes())?;
+    }
+    for value in [files_offset, files.len(), offset, table.len()] {
+        writer.write_all(&(value as u64).to_le_bytes())?;
+    }
+    writer.flush()?;
+    drop(writer);
+    drop(old_opt);
+
+    if let Err(err) = std::fs::rename(&tmp_path, &index_path) {
+        fail!("Could not write index '{}': {}", index_path.display(), err);
+    }
+    Ok((files.len(), read_count))
+}
+
+//Trigrams are case-insensitive for ASCII, the same index serves case-sensitive and insensitive searches
+fn content_trigrams_(content: &[u8]) -> Vec<Trigram> {
+    let content = content.to_ascii_lowercase();
+    let mut trigrams: Vec<Trigram> = content.windows(3).map(trigram_).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+//Trigrams that each match of `pattern` contains, an empty result means that no file can be skipped
+fn query_trigrams_(pattern: &str, case_sensitive: bool) -> Vec<Trigram> {
+    let hir = match regex_syntax::ParserBuilder::new()
+        .case_insensitive(!case_sensitive)
+        .utf8(false)
+        .build()
+        .parse(pattern)
+    {
+        Err(_) => return vec![],
+        Ok(hir) => hir,
+    };
+    let literals = Literals::of(&hir);
+    let mut trigrams: Vec<Trigram> = literals
+        .into_required_()
+        .iter()
+        .flat_map(|literal| literal.windows(3).map(trigram_))
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+fn trigram_(bytes: &[u8]) -> Trigram {
+    (bytes[0] as Trigram) << 16 | (bytes[1] as Trigram) << 8 | bytes[2] as Trigram
+}
+
+//Lowercased literals that each match contains, `exact` is set when it is the complete match
+struct Literals {
+    exact: Option<Vec<u8>>,
+    required: Vec<Vec<u8>>,
+}
+
+impl Literals {
+    fn of(hir: &Hir) -> Literals {
+        let exact = |bytes: Vec<u8>| Literals {
+            exact: Some(bytes),
+            required: vec![],
+        };
+        let none = || Literals {
+            exact: None,
+            required: vec![],
+        };
+        match hir.kind() {
+            HirKind::Empty | HirKind::Look(_) => exact(vec![]),
+            HirKind::Literal(literal) => exact(literal.0.to_ascii_lowercase()),
+            HirKind::Class(class) => match single_byte_(class) {
+                None => none(),
+                Some(byte) => exact(vec![byte]),
+            },
+            HirKind::Capture(capture) => Literals::of(&capture.sub),
+            HirKind::Repetition(repetition) => {
+                let sub = Literals::of(&repetition.sub);
+                if repetition.min == 1 && repetition.max == Some(1) {
+                    sub
+                } else if repetition.min >= 1 {
+                    Literals {
+                        exact: None,
+                        required: sub.into_required_(),
+                    }
+                } else {
+                    none()
+                }
+            }
+            HirKind::Concat(subs) => {
+                //Adjacent exact parts are joined into a longer literal
+                let mut run = vec![];
+                let mut required = vec![];
+                let mut is_exact = true;
+                for sub in subs.iter() {
+                    let sub = Literals::of(sub);
+                    match sub.exact {
+                        Some(bytes) => run.extend(bytes),
+                        None => {
+                            required.push(std::mem::take(&mut run));
+                            required.extend(sub.required);
+                            is_exact = false;
+                        }
+                    }
+                }
+                if is_exact {
+                    Literals {
+                        exact: Some(run),
+                        required,
+                    }
+                } else {
+                    required.push(run);
+                    Literals {
+                        exact: None,
+                        required,
+                    }
+                }
+            }
+            HirKind::Alternation(_) => none(),
+        }
+    }
+
+    fn into_required_(self) -> Vec<Vec<u8>> {
+        let mut required = self.required;
+        required.extend(self.exact);
+        required
+    }
+}
+
+//Case-insensitive literals are parsed into classes like `[Aa]`, these are treated as a single lowercase byte.
+//Classes with non-ASCII characters, eg, `[Kk\u{212a}]`, cannot be represented by a single byte.
+fn single_byte_(class: &Class) -> Option<u8> {
+    let mut bytes = vec![];
+    match class {
+        Class::Unicode(class) => {
+            for range in class.ranges() {
+                for ch in range.start()..=range.end() {
+                    if !ch.is_ascii() || bytes.len() >= 2 {
+                        return None;
+                    }
+                    bytes.push(ch as u8);
+                }
+            }
+        }
+        Class::Bytes(class) => {
+            for range in class.ranges() {
+                for byte in range.start()..=range.end() {
+                    if bytes.len() >= 2 {
+                        return None;
+                    }
+                    bytes.push(byte);
+                }
+            }
+        }
+    }
+    let first = bytes.first()?.to_ascii_lowercase();
+    bytes
+        .iter()
+        .all(|byte| byte.to_ascii_lowercase() == first)
+        .then_some(first)
+}
+
+struct Varints<'a>(&'a [u8]);
+
+impl Varints<'_> {
+    fn next(&mut self) -> Option<u64> {
+        let mut value = 0_u64;
+        for (ix, &byte) in self.0.iter().enumerate().take(10) {
+            value |= ((byte & 0x7f) as u64) << (7 * ix);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[ix + 1..];
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, size: usize) -> Option<&[u8]> {
+        if size > self.0.len() {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(size);
+        self.0 = rest;
+        Some(bytes)
+    }
+}
+
+fn write_varint_(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_u32_(bytes: &[u8], offset: usize) -> u32 {
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buffer)
+}
+
+fn read_u64_(bytes: &[u8], offset: usize) -> u64 {
+    let mut buffer = [0; 8];
+    buffer.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buffer)
+}
+
+#[test]
+fn test_query_trigrams() {
+    let scns = [
+        ("needle", true, vec!["dle", "edl", "eed", "nee"]),
+        ("Needle", false, vec!["dle", "edl", "eed", "nee"]),
+        ("\\bfoo_?bar\\d+", true, vec!["bar", "foo"]),
+        ("(abc)+x.*yz", true, vec!["abc"]),
+        ("ab|cd", true, vec![]),
+        ("a.c", true, vec![]),
+        //`k` matches the Kelvin sign as well when case-insensitive
+        ("ask", false, vec![]),
+        ("ask", true, vec!["ask"]),
+    ];
+    for (pattern, case_sensitive, expected) in scns.iter() {
+        let expected: Vec<Trigram> = expected.iter().map(|s| trigram_(s.as_bytes())).collect();
+        assert_eq!(
+            query_trigrams_(pattern, *case_sensitive),
+            expected,
+            "{}",
+            pattern
+        );
+    }
+}
+
+#[test]
+fn test_index() -> Result<()> {
+    let tmp = crate::util::TempDir::new("index")?;
+    let root = &tmp.path;
+    std::fs::write(root.join("a.txt"), "needle in a haystack")?;
+    std::fs::write(root.join("b.txt"), "only hay")?;
+
+    let options = Options::default();
+    assert_eq!(update_(root, &options, true)?, (2, 2));
+    assert_eq!(update_(root, &options, false)?, (2, 0));
+
+    let search = crate::search::Search::new("NEEDLE", false, false)?;
+    let file_data = file::Data::new(Some(search), false, None);
+    let filter = Filter::new(root, &options, &file_data)?.unwrap();
+    assert!(filter.keep(&root.join("a.txt")));
+    assert!(!filter.keep(&root.join("b.txt")));
+    //Files that changed or are not indexed are kept
+    assert!(filter.keep(&root.join("c.txt")));
+    std::fs::write(root.join("b.txt"), "hay with a needle")?;
+    assert!(filter.keep(&root.join("b.txt")));
+
+    assert_eq!(update_(root, &options, false)?, (2, 1));
+    let filter = Filter::new(root, &options, &file_data)?.unwrap();
+    assert!(filter.keep(&root.join("b.txt")));
+
+    //Empty and truncated indexes are reported as corrupt
+    let content = std::fs::read(root.join(INDEX_FILENAME))?;
+    for size in [0, 4, content.len() - 1].iter() {
+        std::fs::write(root.join(INDEX_FILENAME), &content[..*size])?;
+        let err = Filter::new(root, &options, &file_data).err().unwrap();
+        assert!(err.to_string().contains("is corrupt"));
+    }
+
+    Ok(())
+}
@@ -1,29 +1,293 @@
 #[macro_use]
 pub mod util;
+mod archive;
 pub mod cli;
+pub mod config;
+mod context;
+pub mod editor;
+pub mod encoding;
 pub mod file;
 mod folder;
+pub mod hyperlink;
+mod index;
 mod line;
+pub mod picker;
 pub mod search;
+pub mod template;
+mod terminal;
+pub mod theme;
+pub mod tui;
+pub mod watch;
 extern crate colored;
 
 use crate::line::Line;
-use crate::util::Result;
+use crate::util::{MyError, Result};
 use atty::Stream;
-use colored::Colorize;
-use std::io::BufRead;
+use std::io::Read;
 use std::io::Write;
 
-pub fn process_folder<P>(root: P, options: &cli::Options, file_data: &mut file::Data) -> Result<()>
-where
-    P: AsRef<std::path::Path>,
-{
-    let paths = folder::Scanner::new(root, &options)?.scan()?;
+//Outputs the lines found by file and Stdin searches, in the layout selected via the options
+struct Printer<'a> {
+    options: &'a cli::Options,
+    search: &'a search::Search,
+    replace_opt: &'a Option<search::Replace>,
+    extract_opt: &'a Option<search::Extract>,
+    template_opt: &'a Option<template::Template>,
+    theme: &'a theme::Theme,
+    hyperlink_opt: &'a Option<hyperlink::Hyperlink>,
+    //Console output has a header per file and separators between groups of lines
+    console_output: bool,
+    //Long lines are only shortened for console output to a terminal, other output is meant to be processed further
+    shorten_lines: bool,
+    //Output is kept back instead of printed when present, see record()
+    recorded_opt: Option<std::cell::RefCell<Vec<file::Recorded>>>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(
+        options: &'a cli::Options,
+        search: &'a search::Search,
+        replace_opt: &'a Option<search::Replace>,
+        extract_opt: &'a Option<search::Extract>,
+        template_opt: &'a Option<template::Template>,
+        theme: &'a theme::Theme,
+        hyperlink_opt: &'a Option<hyperlink::Hyperlink>,
+    ) -> Printer<'a> {
+        let stdout_is_tty = atty::is(Stream::Stdout);
+        //A template determines the complete output
+        let console_output =
+            options.console_output.unwrap_or(stdout_is_tty) && template_opt.is_none();
+        Printer {
+            options,
+            search,
+            replace_opt,
+            extract_opt,
+            console_output,
+            shorten_lines: console_output && stdout_is_tty,
+            template_opt,
+            theme,
+            hyperlink_opt,
+            recorded_opt: None,
+        }
+    }
+
+    //Keeps the output back, to output it later in a different order
+    fn record(mut self) -> Printer<'a> {
+        self.recorded_opt = Some(std::cell::RefCell::new(vec![]));
+        self
+    }
+
+    fn into_recorded(self) -> Option<Vec<file::Recorded>> {
+        self.recorded_opt.map(|recorded| recorded.into_inner())
+    }
+
+    //Returns true when `event` was recorded instead of printed
+    fn recorded_(&self, event: impl FnOnce() -> file::Recorded) -> bool {
+        match &self.recorded_opt {
+            None => false,
+            Some(recorded) => {
+                recorded.borrow_mut().push(event());
+                true
+            }
+        }
+    }
+
+    fn filename(&self, path: &std::path::Path) {
+        if self.recorded_(|| file::Recorded::Filename) {
+            return;
+        }
+        if self.options.null_separated_output {
+            print!("{}\0", path.display());
+        } else {
+            let text = path.display();
+            println!("{}", link_(self.hyperlink_opt, path, &text, None, None));
+        }
+    }
+
+    fn header(&self, path: &std::path::Path) {
+        if self.recorded_(|| file::Recorded::Header) {
+            return;
+        }
+        if self.console_output {
+            let text = self.theme.path.paint(&path.display().to_string());
+            println!("{}", link_(self.hyperlink_opt, path, &text, None, None));
+        }
+    }
+
+    fn separator(&self) {
+        if self.recorded_(|| file::Recorded::Separator) {
+            return;
+        }
+        if self.console_output {
+            println!("{}", self.theme.separator.paint("..."));
+        }
+    }
+
+    fn footer(&self) {
+        if self.recorded_(|| file::Recorded::Footer) {
+            return;
+        }
+        if self.console_output {
+            println!();
+        }
+    }
+
+    //`content` is the slice for `line`, `path_opt` is None for Stdin
+    fn line(&self, line: &Line, content: &[u8], path_opt: Option<&std::path::Path>) {
+        let recorded = self.recorded_(|| {
+            let mut line = line.clone();
+            line.range = 0..content.len();
+            file::Recorded::Line(line, content.to_vec())
+        });
+        if recorded {
+            return;
+        }
+
+        if let Some(template) = self.template_opt {
+            let path = path_opt.map(|path| path.display().to_string());
+            line.print_template(
+                content,
+                self.search,
+                self.replace_opt,
+                template,
+                path.as_deref().unwrap_or(""),
+            );
+            return;
+        }
+
+        let prefix = match path_opt {
+            Some(path) if !self.console_output => {
+                let col_opt = line.matches.first().map(|m| m.start + 1);
+                let text = path.display();
+                let text = link_(self.hyperlink_opt, path, &text, Some(line.nr), col_opt);
+                format!("{}:", text)
+            }
+            _ => String::new(),
+        };
+        //In passthrough mode, lines without match are output completely
+        let whole_line = self.options.passthrough && line.matches.is_empty();
+        let match_only = self.options.output_only == Some(cli::OutputOnly::Match);
+        match self.extract_opt {
+            Some(extract) if !whole_line => line.print_extract(
+                content,
+                self.search,
+                extract,
+                &prefix,
+                path_opt.is_some(),
+                self.theme,
+            ),
+            _ if match_only && !whole_line && path_opt.is_none() => {
+                line.print_colored_match(content, self.theme)
+            }
+            _ => {
+                let (max_columns, match_window_opt) = if self.shorten_lines {
+                    (self.options.max_columns, self.options.match_window_opt)
+                } else {
+                    (0, None)
+                };
+                print!("{}", prefix);
+                line.print_colored(
+                    content,
+                    self.search,
+                    self.replace_opt,
+                    self.theme,
+                    max_columns,
+                    match_window_opt,
+                );
+            }
+        }
+    }
+}
+
+//Creates the color theme from the options, falling back to the environment variables
+pub fn create_theme(options: &cli::Options) -> Result<theme::Theme> {
+    let name = match &options.theme_opt {
+        Some(name) => name.clone(),
+        None => std::env::var(theme::THEME_ENV).unwrap_or_else(|_| "dark".to_string()),
+    };
+    let mut theme = theme::Theme::preset(&name)?;
+    if let Ok(overrides) = std::env::var(theme::COLORS_ENV) {
+        theme.apply(&overrides)?;
+    }
+    for overrides in &options.colors_vec {
+        theme.apply(overrides)?;
+    }
+    Ok(theme)
+}
+
+//Creates the hyperlink format for printed paths, only when they are printed to a terminal
+pub fn create_hyperlink(options: &cli::Options) -> Result<Option<hyperlink::Hyperlink>> {
+    match options.hyperlink_opt.as_deref() {
+        None | Some("none") => Ok(None),
+        Some(_) if !atty::is(Stream::Stdout) => Ok(None),
+        Some(format) => Ok(Some(hyperlink::Hyperlink::new(format)?)),
+    }
+}
+
+//Wraps `text` in a hyperlink to `path`, if requested
+fn link_(
+    hyperlink_opt: &Option<hyperlink::Hyperlink>,
+    path: &std::path::Path,
+    text: &dyn std::fmt::Display,
+    line_opt: Option<u64>,
+    col_opt: Option<usize>,
+) -> String {
+    match hyperlink_opt {
+        None => text.to_string(),
+        Some(hyperlink) => {
+            let url = hyperlink.url(path, line_opt, col_opt);
+            hyperlink.wrap(&text.to_string(), &url)
+        }
+    }
+}
+
+//Creates the output template that is requested via the options, if any
+pub fn create_template(
+    options: &cli::Options,
+    search: &search::Search,
+) -> Result<Option<template::Template>> {
+    let has_replace = options.replace_opt.is_some();
+    let template = match (&options.template_opt, &options.line_template_opt) {
+        (None, None) => return Ok(None),
+        (Some(_), Some(_)) => fail!("Only one of --template and --line-template can be used"),
+        (Some(template), None) => {
+            template::Template::new(template, true, &search.regex, has_replace)?
+        }
+        (None, Some(template)) => {
+            template::Template::new(template, false, &search.regex, has_replace)?
+        }
+    };
+    Ok(Some(template))
+}
+
+//Creates the capture group output that is requested via the options, if any
+pub fn create_extract(
+    options: &cli::Options,
+    search: &search::Search,
+) -> Result<Option<search::Extract>> {
+    let extract = match &options.group_template_opt {
+        Some(template) => search::Extract::from_template(template, &search.regex)?,
+        None if options.group_vec.is_empty() => return Ok(None),
+        None => search::Extract::from_groups(
+            &options.group_vec,
+            &options.group_separator,
+            &search.regex,
+        )?,
+    };
+    Ok(Some(extract))
+}
+
+//Processes the files from --files-from or below the roots. When sorting is requested, all files are sorted together.
+pub fn process_paths(options: &cli::Options, file_data: &mut file::Data) -> Result<()> {
+    let paths = scan_paths_(options, file_data)?;
 
     if file_data.search_opt.is_some() {
         for path in &paths {
             process_file(path, options, file_data)?;
         }
+        if options.sort_opt == Some(cli::SortKey::Matches) {
+            output_recordings_(options, file_data);
+        }
     } else {
         for mut path in paths {
             file_data.filepaths.push(path.clone());
@@ -31,13 +295,177 @@ where
                 path = path.strip_prefix(".")?.to_path_buf();
             }
             if options.null_separated_output {
-                print!("{}\0", format!("{}", path.display()));
+                print!("{}\0", path.display());
+            } else {
+                let text = path.display();
+                println!(
+                    "{}",
+                    link_(&file_data.hyperlink_opt, &path, &text, None, None)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+//Reads the filepaths listed in `source`, '-' refers to Stdin
+fn read_filepaths_(source: &str) -> Result<folder::Paths> {
+    let buffer = if source == "-" {
+        let mut buffer = vec![];
+        std::io::stdin().lock().read_to_end(&mut buffer)?;
+        buffer
+    } else {
+        match std::fs::read(source) {
+            Err(err) => fail!("Could not read filepaths from '{}': {}", source, err),
+            Ok(buffer) => buffer,
+        }
+    };
+    Ok(util::split_filepaths(&buffer))
+}
+
+//All paths that are selected via the options, from --files-from or from the roots, sorted if requested
+fn scan_paths_(options: &cli::Options, file_data: &file::Data) -> Result<folder::Paths> {
+    let paths = match &options.files_from_opt {
+        Some(files_from) => read_filepaths_(files_from)?,
+        None => {
+            let roots = if options.roots.is_empty() {
+                vec![".".to_string()]
             } else {
-                println!("{}", format!("{}", path.display()));
+                options.roots.clone()
+            };
+            let mut paths = folder::Paths::new();
+            for root in roots.iter().map(std::path::PathBuf::from) {
+                if root.is_dir() {
+                    let mut root_paths = folder::Scanner::new(&root, options)?.scan()?;
+                    if let Some(filter) = index::Filter::new(&root, options, file_data)? {
+                        let count = root_paths.len();
+                        root_paths.retain(|path| filter.keep(path));
+                        if options.verbose_level >= 1 {
+                            println!(
+                                "The index skips {} of {} files",
+                                count - root_paths.len(),
+                                count
+                            );
+                        }
+                    }
+                    paths.extend(root_paths);
+                } else if options.output_only != Some(cli::OutputOnly::Folders) {
+                    paths.push(root);
+                }
+            }
+            paths
+        }
+    };
+    Ok(sort_paths_(paths, options))
+}
+
+//Sorts paths on the key that is requested via the options, ties are ordered by path.
+//Sorting on matches happens while outputting, see output_recordings_(), the paths are ordered by path meanwhile.
+fn sort_paths_(paths: folder::Paths, options: &cli::Options) -> folder::Paths {
+    let sort_key = match options.sort_opt {
+        None => return paths,
+        Some(sort_key) => sort_key,
+    };
+    let mut keyed_paths: Vec<(u128, std::path::PathBuf)> = paths
+        .into_iter()
+        .map(|path| {
+            let md_opt = std::fs::metadata(&path).ok();
+            let key = match sort_key {
+                cli::SortKey::Path | cli::SortKey::Matches => 0,
+                cli::SortKey::Mtime => md_opt
+                    .and_then(|md| md.modified().ok())
+                    .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or(0, |duration| duration.as_nanos()),
+                cli::SortKey::Size => md_opt.map_or(0, |md| md.len() as u128),
+            };
+            (key, path)
+        })
+        .collect();
+    let descending = options.sort_descending;
+    keyed_paths.sort_by(|(key_a, path_a), (key_b, path_b)| match sort_key {
+        cli::SortKey::Path if descending => path_b.cmp(path_a),
+        _ if descending => key_b.cmp(key_a).then_with(|| path_a.cmp(path_b)),
+        _ => key_a.cmp(key_b).then_with(|| path_a.cmp(path_b)),
+    });
+    keyed_paths.into_iter().map(|(_, path)| path).collect()
+}
+
+//Outputs the recorded files, sorted on their number of matches. Ties are ordered by path.
+fn output_recordings_(options: &cli::Options, file_data: &mut file::Data) {
+    let mut recordings = std::mem::take(&mut file_data.recordings);
+    let descending = options.sort_descending;
+    recordings.sort_by(|a, b| {
+        let order = a.match_count.cmp(&b.match_count);
+        let order = if descending { order.reverse() } else { order };
+        order.then_with(|| a.path.cmp(&b.path))
+    });
+    file_data.filepaths = recordings.iter().map(|r| r.path.clone()).collect();
+
+    let printer = Printer::new(
+        options,
+        file_data.search_opt.as_ref().unwrap(),
+        &file_data.replace_opt,
+        &file_data.extract_opt,
+        &file_data.template_opt,
+        &file_data.theme,
+        &file_data.hyperlink_opt,
+    );
+    for recording in recordings.iter() {
+        let path = recording.path.as_path();
+        for event in recording.events.iter() {
+            match event {
+                file::Recorded::Header => printer.header(path),
+                file::Recorded::Line(line, content) => printer.line(line, content, Some(path)),
+                file::Recorded::Separator => printer.separator(),
+                file::Recorded::Footer => printer.footer(),
+                file::Recorded::Filename => printer.filename(path),
+            }
+        }
+    }
+}
+
+pub fn process_why_ignored<P>(path: P, options: &cli::Options) -> Result<()>
+where
+    P: AsRef<std::path::Path>,
+{
+    let path = path.as_ref();
+    let abs_path = std::fs::canonicalize(path)?;
+
+    let roots = if options.roots.is_empty() {
+        vec![".".to_string()]
+    } else {
+        options.roots.clone()
+    };
+    //Explain with respect to the first root that contains path
+    for root in roots.iter() {
+        if abs_path.starts_with(std::fs::canonicalize(root)?) {
+            match folder::Scanner::new(root, options)?.why_ignored(path)? {
+                None => println!("'{}' is not ignored", path.display()),
+                Some(reason) => println!("{}", reason),
             }
+            return Ok(());
         }
     }
 
+    fail!("'{}' is not located below any root", path.display())
+}
+
+pub fn process_index(command: cli::IndexCommand, options: &cli::Options) -> Result<()> {
+    let roots = if options.roots.is_empty() {
+        vec![".".to_string()]
+    } else {
+        options.roots.clone()
+    };
+    for root in roots.iter().map(std::path::Path::new) {
+        if !root.is_dir() {
+            fail!(
+                "Only folders can be indexed, '{}' is not a folder",
+                root.display()
+            );
+        }
+        index::run(command, root, options)?;
+    }
     Ok(())
 }
 
@@ -50,148 +478,388 @@ pub fn process_file(
         return Ok(());
     }
 
-    let console_output = options.console_output.unwrap_or(atty::is(Stream::Stdout));
+    if options.search_compressed {
+        if let Some(kind) = archive::Kind::from_path(path) {
+            if kind.is_archive() {
+                return process_archive_(path, kind, options, file_data);
+            }
+        }
+    }
+
+    if streamable_(path, options, file_data) {
+        if let Err(err) = process_stream_(path, options, file_data) {
+            if err.is::<file::SizeChanged>() {
+                eprintln!("Error: {}", err);
+            } else if options.verbose_level >= 1 {
+                println!("Warning: Stopped searching '{}': {}", path.display(), err);
+            }
+        }
+        return Ok(());
+    }
 
     match file_data.load(path) {
-        Err(_) => {
-            if options.verbose_level >= 1 {
+        Err(err) => {
+            if err.is::<file::SizeChanged>() {
+                eprintln!("Error: {}", err);
+            } else if options.verbose_level >= 1 {
                 println!(
-                    "Warning: Skipping '{}', could not load file",
-                    path.display()
+                    "Warning: Skipping '{}', could not load file: {}",
+                    path.display(),
+                    err
                 );
             }
         }
 
-        Ok(()) => {
-            file_data.split_in_lines()?;
-            if file_data.search_for_matches() ^ options.invert_pattern {
-                if file_data.path.starts_with(".") {
-                    file_data.path = file_data.path.strip_prefix(".")?.to_path_buf();
-                }
+        Ok(()) => process_content_(options, file_data)?,
+    }
 
-                let search = file_data.search_opt.as_ref().unwrap();
-                if options.output_only == Some(cli::OutputOnly::Filenames) {
-                    let fp = file_data.path.clone();
-                    if options.null_separated_output {
-                        print!("{}\0", format!("{}", fp.display()));
-                    } else {
-                        println!("{}", format!("{}", fp.display()));
-                    }
-                    file_data.filepaths.push(fp);
-                } else {
-                    if console_output {
-                        println!("{}", format!("{}", file_data.path.display()).green().bold());
-                    }
-                    let content = file_data.content.as_slice();
-                    //Iterator that is meant to be options.output_before behind the
-                    //one driving the for loop. `delay` indicates the actual delay.
-                    let mut delayed_line_iter = file_data.lines.iter();
-                    let mut delay = 0;
-                    //As long as output_count is Some(>0), we will output
-                    let mut output_count = None;
-                    for line in file_data.lines.iter() {
-                        if !line.matches.is_empty() {
-                            output_count = Some(delay + options.output_after + 1);
-                        }
+    Ok(())
+}
 
-                        if let Some(cnt) = output_count {
-                            let delayed_line = delayed_line_iter.next().unwrap();
-                            if cnt > 0 {
-                                if !console_output {
-                                    print!("{}:", file_data.path.display());
-                                }
-                                delayed_line.print_colored(
-                                    delayed_line.as_slice(content),
-                                    search,
-                                    &file_data.replace_opt,
-                                );
-                                output_count = Some(cnt - 1);
-                            } else {
-                                if console_output {
-                                    println!("...");
-                                }
-                                output_count = None;
-                            }
-                        } else if delay < options.output_before {
-                            delay += 1;
-                        } else {
-                            let _ = delayed_line_iter.next();
-                        }
-                    }
-                    if console_output {
-                        println!("");
-                    }
-                }
+//Large files are searched record per record, unless the whole content is needed for replacement or decompression
+fn streamable_(path: &std::path::Path, options: &cli::Options, file_data: &file::Data) -> bool {
+    if file_data.search_opt.is_none() {
+        return false;
+    }
+    if file_data.replace_opt.is_some() && !options.simulate_replace {
+        return false;
+    }
+    //Passthrough output of a file is only started once we know it matches
+    if options.passthrough {
+        return false;
+    }
+    if file_data.decompress {
+        if let Some(archive::Kind::Compressed(_)) = archive::Kind::from_path(path) {
+            return false;
+        }
+    }
+    match std::fs::metadata(path) {
+        Err(_) => false,
+        Ok(md) => md.is_file() && md.len() > options.stream_threshold,
+    }
+}
 
-                if file_data.replace_opt.is_some() {
-                    if !options.simulate_replace {
-                        file_data.replace_and_write()?;
-                    }
+//Searches a file while reading it, only the current record and the context are kept in memory
+fn process_stream_(
+    path: &std::path::Path,
+    options: &cli::Options,
+    file_data: &mut file::Data,
+) -> Result<()> {
+    let mut f = std::fs::File::open(path)?;
+    let md_size = f.metadata()?.len();
+    let decoder = encoding_rs_io::DecodeReaderBytesBuilder::new()
+        .encoding(file_data.encoding_opt)
+        .bom_override(true)
+        .utf8_passthru(true)
+        .strip_bom(true)
+        .build(&mut f);
+    let mut reader = std::io::BufReader::with_capacity(64 * 1024, decoder);
+
+    let path = path.strip_prefix(".").unwrap_or(path);
+    let search = file_data.search_opt.as_ref().unwrap();
+    let list_only = options.output_only == Some(cli::OutputOnly::Filenames);
+    let output_lines = !list_only && !options.invert_pattern;
+    let sort_on_matches = options.sort_opt == Some(cli::SortKey::Matches);
+
+    let mut printer = Printer::new(
+        options,
+        search,
+        &file_data.replace_opt,
+        &file_data.extract_opt,
+        &file_data.template_opt,
+        &file_data.theme,
+        &file_data.hyperlink_opt,
+    );
+    if sort_on_matches || file_data.record_output {
+        printer = printer.record();
+    }
+
+    let mut context = context::Context::new(options.output_before, options.output_after);
+    let mut buffer: Vec<u8> = vec![];
+    let mut line_nr = 0;
+    let mut match_count = 0;
+    let mut found_match = false;
+    let mut completed = true;
+    loop {
+        if line::read_record(&mut reader, file_data.separator, &mut buffer)? == 0 {
+            break;
+        }
+        line_nr += 1;
+
+        let (size, eol_size) = line::next_record(&buffer, file_data.separator);
+        let mut line = Line::new(line_nr, 0, size, eol_size);
+        let is_match = line.search_for(search, &buffer);
+        match_count += line.matches.len();
+        if is_match && options.open && !options.invert_pattern {
+            file_data.locations.push(editor::Location {
+                path: path.to_path_buf(),
+                line: line_nr,
+                col: line.matches[0].start + 1,
+                text: line::display_text(line.as_text_slice(&buffer)).into_owned(),
+            });
+        }
+
+        if is_match && !found_match {
+            found_match = true;
+            if output_lines {
+                printer.header(path);
+            } else if !sort_on_matches {
+                //Without individual lines to output, we can stop at the first match
+                completed = false;
+                break;
+            }
+        }
+
+        if output_lines {
+            let record = (line, std::mem::take(&mut buffer));
+            context.push(record, is_match, |event| match event {
+                context::Event::Record((line, buffer)) => printer.line(&line, &buffer, Some(path)),
+                context::Event::Separator => printer.separator(),
+            });
+        }
+        buffer.clear();
+    }
+    drop(reader);
+
+    if found_match ^ options.invert_pattern {
+        if list_only {
+            printer.filename(path);
+        } else {
+            if !output_lines {
+                printer.header(path);
+            }
+            printer.footer();
+        }
+        file_data.filepaths.push(path.to_path_buf());
+        if let Some(events) = printer.into_recorded() {
+            file_data.recordings.push(file::Recording {
+                path: path.to_path_buf(),
+                match_count,
+                events,
+            });
+        }
+    }
+
+    if completed && f.metadata()?.len() != md_size {
+        return Err(Box::new(file::SizeChanged {
+            path: path.to_path_buf(),
+        }));
+    }
+
+    Ok(())
+}
+
+//Searches each member of an archive that passes the file filters, as if it was a regular file
+fn process_archive_(
+    path: &std::path::Path,
+    kind: archive::Kind,
+    options: &cli::Options,
+    file_data: &mut file::Data,
+) -> Result<()> {
+    let scanner = folder::Scanner::new(path, options)?;
+    //Errors while processing a member, eg, a rejected replacement, are not related to reading the archive
+    let mut process_err_opt = None;
+    let read_res = archive::for_each_member(path, kind, |member, content| {
+        let member_path = archive::member_path(path, member);
+        if scanner.file_ok(&member_path) {
+            file_data.load_member(member_path, content);
+            if let Err(err) = process_content_(options, file_data) {
+                process_err_opt = Some(err);
+                fail!("Stopped processing archive");
+            }
+        }
+        Ok(())
+    });
+
+    if let Some(err) = process_err_opt {
+        return Err(err);
+    }
+    if let Err(err) = read_res {
+        if options.verbose_level >= 1 {
+            println!(
+                "Warning: Skipping '{}', could not read archive: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+
+    Ok(())
+}
+
+//Searches, outputs and replaces the content that is currently loaded in file_data
+fn process_content_(options: &cli::Options, file_data: &mut file::Data) -> Result<()> {
+    if file_data.search_for_matches()? ^ options.invert_pattern {
+        if file_data.path.starts_with(".") {
+            file_data.path = file_data.path.strip_prefix(".")?.to_path_buf();
+        }
+        if options.passthrough && options.output_only != Some(cli::OutputOnly::Filenames) {
+            file_data.complete_lines()?;
+        }
+        if options.open && !options.invert_pattern {
+            record_locations_(file_data);
+        }
+
+        let search = file_data.search_opt.as_ref().unwrap();
+        let mut printer = Printer::new(
+            options,
+            search,
+            &file_data.replace_opt,
+            &file_data.extract_opt,
+            &file_data.template_opt,
+            &file_data.theme,
+            &file_data.hyperlink_opt,
+        );
+        if options.sort_opt == Some(cli::SortKey::Matches) || file_data.record_output {
+            printer = printer.record();
+        }
+        if options.output_only == Some(cli::OutputOnly::Filenames) {
+            printer.filename(&file_data.path);
+            file_data.filepaths.push(file_data.path.clone());
+        } else {
+            let content = file_data.content();
+            let path = &file_data.path;
+            printer.header(path);
+            let mut context = context::Context::new(options.output_before, options.output_after);
+            let mut prev_nr = 0;
+            for line in file_data.lines.iter() {
+                //Lines that are not part of any context are not present
+                if line.nr > prev_nr + 1 {
+                    context.skip();
                 }
+                prev_nr = line.nr;
+                let is_match = options.passthrough || !line.matches.is_empty();
+                context.push(line, is_match, |event| match event {
+                    context::Event::Record(line) => {
+                        printer.line(line, line.as_slice(content), Some(path))
+                    }
+                    context::Event::Separator => printer.separator(),
+                });
             }
+            printer.footer();
+            file_data.filepaths.push(file_data.path.clone());
+        }
+        if let Some(events) = printer.into_recorded() {
+            let match_count = file_data.lines.iter().map(|line| line.matches.len()).sum();
+            file_data.recordings.push(file::Recording {
+                path: file_data.path.clone(),
+                match_count,
+                events,
+            });
+        }
+
+        if file_data.replace_opt.is_some() && !options.simulate_replace {
+            file_data.replace_and_write()?;
         }
     }
 
     Ok(())
 }
 
+//Collects the matching lines of the content that is currently loaded, to open the editor at these locations
+fn record_locations_(file_data: &mut file::Data) {
+    let content = file_data.content();
+    let locations: Vec<editor::Location> = file_data
+        .lines
+        .iter()
+        .filter_map(|line| {
+            line.matches.first().map(|m| editor::Location {
+                path: file_data.path.clone(),
+                line: line.nr,
+                col: m.start + 1,
+                text: line::display_text(line.as_text_slice(content)).into_owned(),
+            })
+        })
+        .collect();
+    file_data.locations.extend(locations);
+}
+
 pub fn process_stdin(options: &cli::Options) -> Result<()> {
     match &options.search_pattern_opt {
         None => Ok(()),
         Some(pattern) => {
             let (stdin, stdout) = (std::io::stdin(), std::io::stdout());
-            let (mut stdin_handle, mut stdout_handle) = (stdin.lock(), stdout.lock());
+            let encoding_opt = match &options.encoding_opt {
+                None => None,
+                Some(label) => Some(encoding::from_label(label)?),
+            };
+            //Input is transcoded into UTF-8 when it starts with a BOM or when an encoding is set.
+            //Other input is passed through as-is.
+            let decoder = encoding_rs_io::DecodeReaderBytesBuilder::new()
+                .encoding(encoding_opt)
+                .bom_override(true)
+                .utf8_passthru(true)
+                .strip_bom(true)
+                .build(stdin.lock());
+            let (mut stdin_handle, mut stdout_handle) =
+                (std::io::BufReader::new(decoder), stdout.lock());
             let search =
-                search::Search::new(&pattern, options.word_boundary, options.case_sensitive)
+                search::Search::new(pattern, options.word_boundary, options.case_sensitive)
                     .unwrap();
 
             let replace_opt = options
                 .replace_opt
                 .as_ref()
                 .map(|s| search::Replace::new(s, &options.capture_group_prefix_opt));
+            let extract_opt = create_extract(options, &search)?;
+            let template_opt = create_template(options, &search)?;
+            let theme = create_theme(options)?;
+            let printer = Printer::new(
+                options,
+                &search,
+                &replace_opt,
+                &extract_opt,
+                &template_opt,
+                &theme,
+                &None,
+            );
             let stdout_is_tty = atty::is(Stream::Stdout);
+            let mut context = context::Context::new(options.output_before, options.output_after);
 
             let mut buffer: Vec<u8> = vec![];
             let mut buffer_replaced: Vec<u8> = vec![];
             let mut line_nr = 0;
 
-            while let Ok(size) = stdin_handle.read_until(0x0a_u8, &mut buffer) {
+            while let Ok(size) =
+                line::read_record(&mut stdin_handle, options.separator, &mut buffer)
+            {
                 if size == 0 {
                     break;
                 }
 
                 line_nr += 1;
 
-                let mut line = Line::new(line_nr, 0, buffer.len());
+                let (size, eol_size) = line::next_record(&buffer, options.separator);
+                let mut line = Line::new(line_nr, 0, size, eol_size);
 
                 let found_match = line.search_for(&search, &buffer) ^ options.invert_pattern;
 
-                if replace_opt.is_some() && !stdout_is_tty {
-                    //When we are _replacing_ with _redirected output_, we will keep _all_ the input lines,
-                    //also those that do not match
-                    if found_match && replace_opt.is_some() {
-                        if options.output_only == Some(cli::OutputOnly::Match) {
-                            line.only_matches(&buffer, &mut buffer_replaced);
+                match &replace_opt {
+                    Some(replace) if !stdout_is_tty => {
+                        //When we are _replacing_ with _redirected output_, we will keep _all_ the input lines,
+                        //also those that do not match
+                        if found_match {
+                            if options.output_only == Some(cli::OutputOnly::Match) {
+                                line.only_matches(&buffer, &mut buffer_replaced);
+                            } else {
+                                line.replace_with(&buffer, &search, replace, &mut buffer_replaced);
+                            }
+                            stdout_handle.write_all(&buffer_replaced)?;
                         } else {
-                            line.replace_with(
-                                &buffer,
-                                &search,
-                                replace_opt.as_ref().unwrap(),
-                                &mut buffer_replaced,
-                            );
+                            stdout_handle.write_all(&buffer)?;
                         }
-                        stdout_handle.write(&buffer_replaced)?;
-                    } else {
-                        stdout_handle.write(&buffer)?;
                     }
-                } else {
-                    //Else, we only output matching lines
-                    if found_match {
-                        if options.output_only == Some(cli::OutputOnly::Match) {
-                            line.print_colored_match(&buffer);
-                        } else {
-                            line.print_colored(&buffer, &search, &replace_opt);
-                        }
+                    _ => {
+                        //Else, we only output matching lines and their context, or all lines in passthrough mode.
+                        //The record buffer is moved into the context, which keeps it as long as it might be output.
+                        let record = (line, std::mem::take(&mut buffer));
+                        let is_match = options.passthrough || found_match;
+                        context.push(record, is_match, |event| match event {
+                            context::Event::Record((line, buffer)) => {
+                                printer.line(&line, &buffer, None)
+                            }
+                            context::Event::Separator => printer.separator(),
+                        });
                     }
                 }
 
@@ -1,22 +1,159 @@
-use crate::search::{Replace, Search};
-use crate::util::{MyError, Range};
-use colored::Colorize;
+use crate::cli::Separator;
+use crate::search::{Extract, Replace, Search};
+use crate::template::{Template, Values};
+use crate::theme::Theme;
+use crate::util::{MyError, Range, Result};
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::io::BufRead;
+use std::io::Write as _;
 use std::str::from_utf8;
 
+//Marks text that is left out of a shortened line
+const ELLIPSIS: &str = "...";
+
 pub type Content = Vec<u8>;
 type ContentSlice = [u8];
 
+//Renders bytes as text for display, invalid UTF-8 is shown as `\xNN` escapes.
+//Callers split content at byte positions, hence a multi-byte character that is split is escaped as well.
+pub fn display_text(bytes: &ContentSlice) -> Cow<'_, str> {
+    match from_utf8(bytes) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => {
+            let mut text = String::with_capacity(bytes.len());
+            for chunk in bytes.utf8_chunks() {
+                text.push_str(chunk.valid());
+                for byte in chunk.invalid() {
+                    let _ = write!(text, "\\x{:02x}", byte);
+                }
+            }
+            Cow::Owned(text)
+        }
+    }
+}
+
+//Size of the line terminator at the end of `line`: 2 for `\r\n`, 1 for `\n` and 0 when there is none
+pub fn eol_size(line: &ContentSlice) -> usize {
+    if line.ends_with(b"\r\n") {
+        2
+    } else if line.ends_with(b"\n") {
+        1
+    } else {
+        0
+    }
+}
+
+//Splits off the first record of `content`, returns its size and the size of its terminator
+pub fn next_record(content: &ContentSlice, separator: Separator) -> (usize, usize) {
+    match separator {
+        Separator::Byte(0x0a_u8) => {
+            let size = match content.iter().position(|&v| v == 0x0a_u8) {
+                None => content.len(),
+                Some(ix) => ix + 1,
+            };
+            (size, eol_size(&content[..size]))
+        }
+        Separator::Byte(byte) => match content.iter().position(|&v| v == byte) {
+            None => (content.len(), 0),
+            Some(ix) => (ix + 1, 1),
+        },
+        Separator::Paragraph => next_paragraph_(content),
+    }
+}
+
+//Reads the next record into `buffer`, returns the number of bytes read
+pub fn read_record<R>(
+    reader: &mut R,
+    separator: Separator,
+    buffer: &mut Vec<u8>,
+) -> std::io::Result<usize>
+where
+    R: BufRead,
+{
+    match separator {
+        Separator::Byte(byte) => reader.read_until(byte, buffer),
+        Separator::Paragraph => {
+            let mut size = 0;
+            loop {
+                let line_start = buffer.len();
+                let line_size = reader.read_until(0x0a_u8, buffer)?;
+                size += line_size;
+                if line_size == 0 {
+                    break;
+                }
+                if blank_lines_size_(&buffer[line_start..]) == line_size {
+                    //All subsequent blank lines are part of the terminator as well
+                    loop {
+                        let available = reader.fill_buf()?;
+                        let run = available
+                            .iter()
+                            .take_while(|&&v| v == 0x0a_u8 || v == 0x0d_u8)
+                            .count();
+                        //When all available data is blank, the run might continue in the next chunk
+                        let blank_size = if run > 0 && run == available.len() {
+                            run
+                        } else {
+                            blank_lines_size_(available)
+                        };
+                        if blank_size == 0 {
+                            break;
+                        }
+                        buffer.extend_from_slice(&available[..blank_size]);
+                        reader.consume(blank_size);
+                        size += blank_size;
+                    }
+                    break;
+                }
+            }
+            Ok(size)
+        }
+    }
+}
+
+//A paragraph ends with one or more blank lines, these are all part of its terminator
+fn next_paragraph_(content: &ContentSlice) -> (usize, usize) {
+    let mut offset = 0;
+    while let Some(ix) = content[offset..].iter().position(|&v| v == 0x0a_u8) {
+        let newline_ix = offset + ix;
+        let blank_size = blank_lines_size_(&content[newline_ix + 1..]);
+        if blank_size > 0 {
+            let text_end = newline_ix + 1 - eol_size(&content[..newline_ix + 1]);
+            let size = newline_ix + 1 + blank_size;
+            return (size, size - text_end);
+        }
+        offset = newline_ix + 1;
+    }
+    (content.len(), eol_size(content))
+}
+
+//Size of the blank lines at the start of `content`
+fn blank_lines_size_(content: &ContentSlice) -> usize {
+    let run = content
+        .iter()
+        .take_while(|&&v| v == 0x0a_u8 || v == 0x0d_u8)
+        .count();
+    match content[..run].iter().rposition(|&v| v == 0x0a_u8) {
+        None => 0,
+        Some(ix) => ix + 1,
+    }
+}
+
+#[derive(Clone)]
 pub struct Line {
     pub nr: u64,
     pub range: Range,
+    //The line terminator at the end of range is excluded from matching
+    pub eol_size: usize,
     pub matches: Vec<Range>,
 }
 impl Line {
-    //(start, size) indicate a part from some ContentSlice
-    pub fn new(nr: u64, start: usize, size: usize) -> Line {
+    //(start, size) indicate a part from some ContentSlice, ending with a terminator of eol_size
+    pub fn new(nr: u64, start: usize, size: usize, eol_size: usize) -> Line {
         Line {
             nr,
             range: start..start + size,
+            eol_size,
             matches: vec![],
         }
     }
@@ -25,83 +162,209 @@ impl Line {
         &s[self.range.clone()]
     }
 
+    //Same as as_slice(), without the line terminator
+    pub fn as_text_slice<'a>(&self, s: &'a ContentSlice) -> &'a ContentSlice {
+        &s[self.range.start..self.range.end - self.eol_size]
+    }
+
     pub fn search_for(&mut self, search: &Search, content: &ContentSlice) -> bool {
         self.matches.clear();
         let mut found_match = false;
-        for m in search.regex.find_iter(self.as_slice(content)) {
+        for m in search.regex.find_iter(self.as_text_slice(content)) {
             self.matches.push(m.start()..m.end());
             found_match = true;
         }
         found_match
     }
 
+    //Lines of more than `max_columns` characters are shortened, 0 means no limit.
+    //With `window_opt`, the shortened line shows this number of characters around each match.
     pub fn print_colored(
         &self,
         content: &ContentSlice,
         search: &Search,
         replace_opt: &Option<Replace>,
+        theme: &Theme,
+        max_columns: usize,
+        window_opt: Option<usize>,
     ) {
-        let my_print = |replace_opt: &Option<Replace>| {
-            print!("{}:", format!("{}", self.nr).yellow());
-            let mut offset = 0;
-            for r in self.matches.iter() {
-                if let Ok(normal_str) = from_utf8(&content[offset..r.start]) {
-                    if let Ok(match_str) = from_utf8(&content[r.start..r.end]) {
-                        print!("{}", normal_str);
-                        match &replace_opt {
-                            None => print!("{}", match_str.bright_cyan().bold()),
-                            Some(replace) => {
-                                let caps = search.regex.captures(match_str.as_bytes());
-                                for (capture_ix, part) in &replace.parts {
-                                    if *capture_ix >= 0 {
-                                        if caps.is_none() {
-                                            fail!("Could not search for capture groups, but they are used here. This happens when a search with word boundary does not match in the substring match_str");
-                                        }
-                                        print!(
-                                            "{}",
-                                            from_utf8(
-                                                caps.as_ref()
-                                                    .unwrap()
-                                                    .get(*capture_ix as usize)
-                                                    .unwrap()
-                                                    .as_bytes()
-                                            )
-                                            .unwrap()
-                                            .on_purple()
-                                        );
-                                    }
-                                    print!("{}", part.on_purple());
-                                }
-                            }
-                        }
+        let mut output = String::new();
+        self.write_colored(
+            &mut output,
+            content,
+            search,
+            replace_opt,
+            theme,
+            max_columns,
+            window_opt,
+        );
+        print!("{}", output);
+    }
+
+    //Appends what print_colored() outputs to `output`, eg, to draw it in a terminal UI
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_colored(
+        &self,
+        output: &mut String,
+        content: &ContentSlice,
+        search: &Search,
+        replace_opt: &Option<Replace>,
+        theme: &Theme,
+        max_columns: usize,
+        window_opt: Option<usize>,
+    ) {
+        //The line terminator is not printed, we always end with a newline
+        let content = &content[..content.len() - self.eol_size];
+        let (visibles, omitted) = visible_ranges_(content, &self.matches, max_columns, window_opt);
+        let mut my_print = |replace_opt: &Option<Replace>| -> Result<()> {
+            write!(output, "{}:", theme.line.paint(&self.nr.to_string()))?;
+            for (ix, visible) in visibles.iter().enumerate() {
+                if visible.start > 0 {
+                    write!(output, "{}", theme.separator.paint(ELLIPSIS))?;
+                }
+                let mut offset = visible.start;
+                for r in self.matches.iter() {
+                    //Matches are clipped to the visible part, matches outside of it are skipped
+                    let (start, end) = (r.start.max(visible.start), r.end.min(visible.end));
+                    if start > end || (start == end && r.start < r.end) {
+                        continue;
                     }
+                    write!(output, "{}", display_text(&content[offset..start]))?;
+                    write_match_(output, content, r, start..end, search, replace_opt, theme)?;
+                    offset = end;
                 }
-                offset = r.end;
-            }
-            if let Ok(normal_str) = from_utf8(&content[offset..]) {
-                if replace_opt.is_none() || replace_opt.as_ref().unwrap().prefix.is_none() {
-                    print!("{}", normal_str);
+                let is_last = ix + 1 == visibles.len();
+                if self.matches.is_empty() {
+                    write!(
+                        output,
+                        "{}",
+                        theme
+                            .context
+                            .paint(&display_text(&content[offset..visible.end]))
+                    )?;
+                } else if !is_last
+                    || replace_opt.is_none()
+                    || replace_opt.as_ref().unwrap().prefix.is_none()
+                {
+                    write!(output, "{}", display_text(&content[offset..visible.end]))?;
+                }
+                if is_last && visible.end < content.len() {
+                    write!(output, "{}", theme.separator.paint(ELLIPSIS))?;
                 }
             }
+            if omitted > 0 {
+                let s = if omitted == 1 { "" } else { "es" };
+                write!(
+                    output,
+                    "{}",
+                    theme
+                        .separator
+                        .paint(&format!(" [{} more match{}]", omitted, s))
+                )?;
+            }
+            writeln!(output)?;
 
             Ok(())
         };
 
         my_print(&None).ok();
-        if replace_opt.is_some() {
+        //Lines without match, eg, context lines, are not repeated
+        if replace_opt.is_some() && !self.matches.is_empty() {
             my_print(replace_opt).ok();
         }
     }
 
-    pub fn print_colored_match(&self, content: &ContentSlice) {
+    pub fn print_colored_match(&self, content: &ContentSlice, theme: &Theme) {
         //@todo: make configurable
         // print!("{}:", format!("{}", self.nr).yellow());
         for r in self.matches.iter() {
-            if let Ok(match_str) = from_utf8(&content[r.start..r.end]) {
-                print!("{}", match_str.bright_cyan().bold());
+            print!(
+                "{}",
+                theme.match_.paint(&display_text(&content[r.start..r.end]))
+            );
+        }
+        println!();
+    }
+
+    //Outputs `extract` for each match on a separate line, starting with `prefix` and optionally the line number
+    pub fn print_extract(
+        &self,
+        content: &ContentSlice,
+        search: &Search,
+        extract: &Extract,
+        prefix: &str,
+        with_nr: bool,
+        theme: &Theme,
+    ) {
+        let content = &content[..content.len() - self.eol_size];
+        let mut output = vec![];
+        for r in self.matches.iter() {
+            //The match is searched again in the complete line to take word boundaries into account
+            if let Some(caps) = search.regex.captures_at(content, r.start) {
+                output.clear();
+                extract.expand(&caps, &mut output);
+                print!("{}", prefix);
+                if with_nr {
+                    print!("{}:", theme.line.paint(&self.nr.to_string()));
+                }
+                println!("{}", theme.match_.paint(&display_text(&output)));
             }
         }
-        println!("");
+    }
+
+    //Outputs `template` for each match or for the complete line, without colors
+    pub fn print_template(
+        &self,
+        content: &ContentSlice,
+        search: &Search,
+        replace_opt: &Option<Replace>,
+        template: &Template,
+        path: &str,
+    ) {
+        let text = &content[..content.len() - self.eol_size];
+        let print = |r_opt: Option<&Range>, replacement_opt: Option<&[u8]>| {
+            let caps_opt = r_opt.and_then(|r| search.regex.captures_at(text, r.start));
+            let values = Values {
+                path,
+                nr: self.nr,
+                text,
+                col_opt: r_opt.map(|r| r.start + 1),
+                match_opt: r_opt.map(|r| &text[r.clone()]),
+                caps_opt: caps_opt.as_ref(),
+                replacement_opt,
+            };
+            let mut output = vec![];
+            template.expand(&values, &mut output);
+            output.push(b'\n');
+            std::io::stdout().write_all(&output).ok();
+        };
+
+        let mut replacement = vec![];
+        if template.per_match {
+            for r in self.matches.iter() {
+                let replacement_opt = match replace_opt {
+                    None => None,
+                    Some(replace) => {
+                        replacement.clear();
+                        if let Some(caps) = search.regex.captures_at(text, r.start) {
+                            replace_match_(&caps, replace, &mut replacement);
+                        }
+                        Some(replacement.as_slice())
+                    }
+                };
+                print(Some(r), replacement_opt);
+            }
+        } else {
+            //Match related values refer to the first match on the line
+            let replacement_opt = match replace_opt {
+                None => None,
+                Some(replace) => {
+                    self.replace_with(text, search, replace, &mut replacement);
+                    Some(replacement.as_slice())
+                }
+            };
+            print(self.matches.first(), replacement_opt);
+        }
     }
 
     pub fn replace_with(
@@ -135,3 +398,248 @@ impl Line {
         }
     }
 }
+
+//Writes the `visible` part of match `r`, or its complete replacement
+fn write_match_(
+    output: &mut String,
+    content: &ContentSlice,
+    r: &Range,
+    visible: Range,
+    search: &Search,
+    replace_opt: &Option<Replace>,
+    theme: &Theme,
+) -> Result<()> {
+    match replace_opt {
+        None => write!(
+            output,
+            "{}",
+            theme.match_.paint(&display_text(&content[visible]))
+        )?,
+        Some(replace) => {
+            let caps = search.regex.captures(&content[r.clone()]);
+            for (capture_ix, part) in &replace.parts {
+                if *capture_ix >= 0 {
+                    if caps.is_none() {
+                        fail!("Could not search for capture groups, but they are used here. This happens when a search with word boundary does not match in the substring match_str");
+                    }
+                    write!(
+                        output,
+                        "{}",
+                        theme.replacement.paint(&display_text(
+                            caps.as_ref()
+                                .unwrap()
+                                .get(*capture_ix as usize)
+                                .unwrap()
+                                .as_bytes()
+                        ))
+                    )?;
+                }
+                write!(output, "{}", theme.replacement.paint(part))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+//Moves `pos` forward over `count` characters, continuation bytes of UTF-8 sequences do not count
+fn forward_(text: &ContentSlice, mut pos: usize, count: usize) -> usize {
+    for _ in 0..count {
+        if pos >= text.len() {
+            break;
+        }
+        pos += 1;
+        while pos < text.len() && is_continuation_(text[pos]) {
+            pos += 1;
+        }
+    }
+    pos
+}
+
+fn backward_(text: &ContentSlice, mut pos: usize, count: usize) -> usize {
+    for _ in 0..count {
+        if pos == 0 {
+            break;
+        }
+        pos -= 1;
+        while pos > 0 && is_continuation_(text[pos]) {
+            pos -= 1;
+        }
+    }
+    pos
+}
+
+fn is_continuation_(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+fn column_count_(text: &ContentSlice) -> usize {
+    text.iter().filter(|byte| !is_continuation_(**byte)).count()
+}
+
+//Returns the parts of `text` that are output and the number of matches that are omitted.
+//Text of at most `max_columns` characters is output completely. Longer text is cut after `max_columns`
+//characters, or is reduced to `window` characters around each match, as long as `max_columns` allows.
+fn visible_ranges_(
+    text: &ContentSlice,
+    matches: &[Range],
+    max_columns: usize,
+    window_opt: Option<usize>,
+) -> (Vec<Range>, usize) {
+    if max_columns == 0 || column_count_(text) <= max_columns {
+        return (
+            vec![Range {
+                start: 0,
+                end: text.len(),
+            }],
+            0,
+        );
+    }
+
+    let window = match window_opt {
+        Some(window) if !matches.is_empty() => window,
+        _ => {
+            let end = forward_(text, 0, max_columns);
+            let omitted = matches.iter().filter(|r| r.start >= end).count();
+            return (vec![Range { start: 0, end }], omitted);
+        }
+    };
+
+    let mut visibles: Vec<Range> = vec![];
+    let mut columns = 0;
+    for (ix, r) in matches.iter().enumerate() {
+        if let Some(last) = visibles.last() {
+            if r.end <= last.end {
+                continue;
+            }
+        }
+        let mut visible = backward_(text, r.start, window)..forward_(text, r.end, window);
+        match visibles.last_mut() {
+            //Overlapping windows are merged
+            Some(last) if visible.start <= last.end => {
+                let extra = column_count_(&text[last.end..visible.end]);
+                if columns + extra > max_columns {
+                    return (visibles, matches.len() - ix);
+                }
+                columns += extra;
+                last.end = visible.end;
+            }
+            _ => {
+                let size = column_count_(&text[visible.clone()]);
+                if visibles.is_empty() {
+                    //The first window is always output, if needed cut after `max_columns` characters
+                    visible.end = forward_(text, visible.start, max_columns.min(size));
+                    columns = column_count_(&text[visible.clone()]);
+                } else if columns + size > max_columns {
+                    return (visibles, matches.len() - ix);
+                } else {
+                    columns += size;
+                }
+                visibles.push(visible);
+            }
+        }
+    }
+    (visibles, 0)
+}
+
+//Appends the replacement for a single match to `output`
+fn replace_match_(caps: &regex::bytes::Captures, replace: &Replace, output: &mut Vec<u8>) {
+    for (capture_ix, part) in &replace.parts {
+        if *capture_ix >= 0 {
+            if let Some(m) = caps.get(*capture_ix as usize) {
+                output.extend_from_slice(m.as_bytes());
+            }
+        }
+        output.extend_from_slice(part.as_bytes());
+    }
+}
+
+#[test]
+fn test_display_text() {
+    assert_eq!(display_text(b"plain"), "plain");
+    assert_eq!(display_text(b"caf\xe9 \xff!"), "caf\\xe9 \\xff!");
+    //A split multi-byte character is escaped byte per byte
+    let cafe = "caf\u{e9}".as_bytes();
+    assert_eq!(display_text(&cafe[..4]), "caf\\xc3");
+    assert_eq!(display_text(&cafe[4..]), "\\xa9");
+}
+
+#[test]
+fn test_visible_ranges() {
+    type Ranges = Vec<(usize, usize)>;
+    //Text, matches, max_columns, window_opt, expected visible ranges and omitted count
+    type Scn<'a> = (&'a [u8], Ranges, usize, Option<usize>, Ranges, usize);
+    let text: &[u8] = b"0123456789abcdefghij";
+    let matches = vec![(2, 3), (10, 11), (18, 19)];
+    //Windows are counted in characters, not bytes
+    let text_e9 = "\u{e9}\u{e9}\u{e9}x\u{e9}\u{e9}\u{e9}".as_bytes();
+    let scns: [Scn; 7] = [
+        (text, matches.clone(), 0, None, vec![(0, 20)], 0),
+        (text, matches.clone(), 20, Some(1), vec![(0, 20)], 0),
+        (text, matches.clone(), 8, None, vec![(0, 8)], 2),
+        (text, matches.clone(), 8, Some(1), vec![(1, 4), (9, 12)], 1),
+        (text, matches, 10, Some(4), vec![(0, 7)], 2),
+        (text_e9, vec![(6, 7)], 3, Some(1), vec![(4, 9)], 0),
+        (text_e9, vec![], 2, Some(1), vec![(0, 4)], 0),
+    ];
+    for (text, matches, max_columns, window_opt, exp_visibles, exp_omitted) in scns.iter() {
+        let matches: Vec<Range> = matches.iter().map(|(b, e)| *b..*e).collect();
+        let (visibles, omitted) = visible_ranges_(text, &matches, *max_columns, *window_opt);
+        let visibles: Ranges = visibles.iter().map(|r| (r.start, r.end)).collect();
+        assert_eq!(&visibles, exp_visibles);
+        assert_eq!(omitted, *exp_omitted);
+    }
+}
+
+#[test]
+fn test_eol_size() {
+    assert_eq!(eol_size(b""), 0);
+    assert_eq!(eol_size(b"abc"), 0);
+    assert_eq!(eol_size(b"abc\n"), 1);
+    assert_eq!(eol_size(b"abc\r\n"), 2);
+    assert_eq!(eol_size(b"abc\r"), 0);
+
+    let line = Line::new(1, 2, 4, 2);
+    assert_eq!(line.as_text_slice(b"..ab\r\n"), b"ab");
+}
+
+#[test]
+fn test_next_record() {
+    type Records = Vec<(usize, usize)>;
+    let scns: [(&[u8], Separator, Records); 5] = [
+        (
+            b"a\nb\r\nc",
+            Separator::Byte(0x0a),
+            vec![(2, 1), (3, 2), (1, 0)],
+        ),
+        (b"a\0b\nc\0", Separator::Byte(0x00), vec![(2, 1), (4, 1)]),
+        (b"a;b", Separator::Byte(b';'), vec![(2, 1), (1, 0)]),
+        (
+            b"a\nb\n\n\r\n\nc\n",
+            Separator::Paragraph,
+            vec![(8, 5), (2, 1)],
+        ),
+        (b"\n\na\r\n\r\n", Separator::Paragraph, vec![(2, 2), (5, 4)]),
+    ];
+    for (content, separator, expected) in scns.iter() {
+        let mut records = vec![];
+        let mut rest: &[u8] = content;
+        while !rest.is_empty() {
+            let record = next_record(rest, *separator);
+            records.push(record);
+            rest = &rest[record.0..];
+        }
+        assert_eq!(&records, expected);
+
+        //Reading records from a stream results in the same records
+        let mut reader = std::io::BufReader::with_capacity(3, *content);
+        let mut buffer = vec![];
+        for &(size, eol) in expected.iter() {
+            buffer.clear();
+            assert_eq!(
+                read_record(&mut reader, *separator, &mut buffer).unwrap(),
+                size
+            );
+            assert_eq!(next_record(&buffer, *separator), (size, eol));
+        }
+    }
+}
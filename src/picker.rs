@@ -0,0 +1,569 @@
+use crate::cli::{self, Options};
+use crate::editor::Location;
+use crate::file;
+use crate::line::display_text;
+use crate::terminal::Terminal;
+use crate::theme::Style;
+use crate::util::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::{cursor, queue};
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+//Files are searched in between handling keys, the list is drawn again after searching this long
+const SEARCH_DURATION: Duration = Duration::from_millis(50);
+
+//File, folder or matching line that can be picked
+struct Item {
+    location: Location,
+    //Text that is shown and filtered, without control characters
+    text: String,
+    is_match: bool,
+    //Output of the file in Found::recordings, None when nothing was searched
+    recording_opt: Option<usize>,
+}
+
+impl Item {
+    fn new(location: Location, is_match: bool, recording_opt: Option<usize>) -> Item {
+        let text = if is_match {
+            format!(
+                "{}:{}:{}",
+                location.path.display(),
+                location.line,
+                location.text
+            )
+        } else {
+            location.path.display().to_string()
+        };
+        Item {
+            location,
+            text: printable_(&text),
+            is_match,
+            recording_opt,
+        }
+    }
+
+    //What is output when the item is picked
+    fn output(&self) -> String {
+        let l = &self.location;
+        if self.is_match {
+            format!("{}:{}:{}", l.path.display(), l.line, l.col)
+        } else {
+            l.path.display().to_string()
+        }
+    }
+}
+
+//Items that were found so far, the remaining paths are searched while the user is picking
+struct Found {
+    items: Vec<Item>,
+    recordings: Vec<file::Recording>,
+    paths: std::vec::IntoIter<PathBuf>,
+    searching: bool,
+}
+
+//Lets the user select the files, folders or matches that are found, and outputs them or hands them to the editor.
+//Stdout can be redirected, eg, `vim $(mo --pick)`.
+pub fn pick(options: &Options, file_data: &mut file::Data) -> Result<()> {
+    let paths = crate::scan_paths_(options, file_data)?;
+    //Verbose output while searching would end up on the picker screen
+    let options = &Options {
+        verbose_level: 0,
+        ..options.clone()
+    };
+    let mut found = Found {
+        items: vec![],
+        recordings: vec![],
+        paths: paths.into_iter(),
+        searching: true,
+    };
+    //The output of each file is kept for its preview
+    file_data.record_output = true;
+    let mut term = Terminal::open(options)?;
+    let picked_opt = run_(&mut term, &mut found, options, file_data)?;
+    drop(term);
+
+    //Nothing is output when picking was cancelled
+    if let Some(picked) = picked_opt {
+        for item in picked.iter().map(|&ix| &found.items[ix]) {
+            if options.open {
+                file_data.filepaths.push(item.location.path.clone());
+                file_data.locations.push(item.location.clone());
+            } else {
+                println!("{}", item.output());
+            }
+        }
+    }
+    Ok(())
+}
+
+//Searches the next paths, until SEARCH_DURATION has passed or a key is pressed
+fn collect_items_(found: &mut Found, options: &Options, file_data: &mut file::Data) -> Result<()> {
+    let start = Instant::now();
+    while start.elapsed() < SEARCH_DURATION {
+        let path = match found.paths.next() {
+            None => {
+                found.searching = false;
+                break;
+            }
+            Some(path) => path,
+        };
+        if file_data.search_opt.is_none() || options.output_only == Some(cli::OutputOnly::Folders) {
+            let path = path.strip_prefix(".").unwrap_or(&path).to_path_buf();
+            found.items.push(Item::new(start_of_(path), false, None));
+            continue;
+        }
+
+        //Archives and large files are searched like mo does without picking
+        crate::process_file(&path, options, file_data)?;
+        for recording in std::mem::take(&mut file_data.recordings) {
+            let recording_ix = found.recordings.len();
+            if options.invert_pattern || options.output_only == Some(cli::OutputOnly::Filenames) {
+                //Files are opened at their first match, these are only collected when opening
+                let location = file_data
+                    .locations
+                    .iter()
+                    .find(|l| l.path == recording.path)
+                    .cloned()
+                    .unwrap_or_else(|| start_of_(recording.path.clone()));
+                found
+                    .items
+                    .push(Item::new(location, false, Some(recording_ix)));
+            } else {
+                for event in recording.events.iter() {
+                    if let file::Recorded::Line(line, content) = event {
+                        if let Some(m) = line.matches.first() {
+                            let location = Location {
+                                path: recording.path.clone(),
+                                line: line.nr,
+                                col: m.start + 1,
+                                text: display_text(line.as_text_slice(content)).into_owned(),
+                            };
+                            found
+                                .items
+                                .push(Item::new(location, true, Some(recording_ix)));
+                        }
+                    }
+                }
+            }
+            found.recordings.push(recording);
+        }
+        //Only the picked items are handed to the editor
+        file_data.filepaths.clear();
+        file_data.locations.clear();
+
+        if event::poll(Duration::ZERO)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn start_of_(path: PathBuf) -> Location {
+    Location {
+        path,
+        line: 1,
+        col: 1,
+        text: String::new(),
+    }
+}
+
+//Returns the indices of the picked items, or None when picking was cancelled
+fn run_(
+    term: &mut Terminal,
+    found: &mut Found,
+    options: &Options,
+    file_data: &mut file::Data,
+) -> Result<Option<Vec<usize>>> {
+    let mut state = State::new(&found.items);
+    loop {
+        draw_(term, found, &mut state, options, file_data)?;
+        if found.searching {
+            let count = found.items.len();
+            collect_items_(found, options, file_data)?;
+            state.extend(&found.items, count);
+            if !event::poll(Duration::ZERO)? {
+                continue;
+            }
+        }
+        let items = &found.items;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') | KeyCode::Char('g') if ctrl => return Ok(None),
+            KeyCode::Enter => {
+                if !state.selected.is_empty() {
+                    return Ok(Some(state.selected.iter().copied().collect()));
+                }
+                if let Some((_, ix, _)) = state.matches.get(state.cursor) {
+                    return Ok(Some(vec![*ix]));
+                }
+            }
+            KeyCode::Up => state.move_(-1),
+            KeyCode::Char('p') | KeyCode::Char('k') if ctrl => state.move_(-1),
+            KeyCode::Down => state.move_(1),
+            KeyCode::Char('n') | KeyCode::Char('j') if ctrl => state.move_(1),
+            KeyCode::PageUp => state.move_(-(state.height as isize)),
+            KeyCode::PageDown => state.move_(state.height as isize),
+            KeyCode::Tab => {
+                if let Some((_, ix, _)) = state.matches.get(state.cursor) {
+                    if !state.selected.remove(ix) {
+                        state.selected.insert(*ix);
+                    }
+                    state.move_(1);
+                }
+            }
+            KeyCode::Backspace => {
+                state.query.pop();
+                state.filter(items);
+            }
+            KeyCode::Char('u') if ctrl => {
+                state.query.clear();
+                state.filter(items);
+            }
+            KeyCode::Char(ch) if !ctrl => {
+                state.query.push(ch);
+                state.filter(items);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct State {
+    query: String,
+    //Score and index of the items that match the query, best match first, with the positions of the matched characters
+    matches: Vec<(i64, usize, Vec<usize>)>,
+    selected: BTreeSet<usize>,
+    cursor: usize,
+    //First match that is shown
+    offset: usize,
+    //Number of matches that fit on the screen
+    height: usize,
+}
+
+impl State {
+    fn new(items: &[Item]) -> State {
+        let mut state = State {
+            query: String::new(),
+            matches: vec![],
+            selected: BTreeSet::new(),
+            cursor: 0,
+            offset: 0,
+            height: 1,
+        };
+        state.filter(items);
+        state
+    }
+
+    fn filter(&mut self, items: &[Item]) {
+        self.matches.clear();
+        self.extend(items, 0);
+        self.cursor = 0;
+        self.offset = 0;
+    }
+
+    //Adds the items from `start` on that match the query, the cursor stays where it is
+    fn extend(&mut self, items: &[Item], start: usize) {
+        let query = &self.query;
+        self.matches.extend(
+            items
+                .iter()
+                .enumerate()
+                .skip(start)
+                .filter_map(|(ix, item)| {
+                    fuzzy_match_(query, &item.text).map(|(score, positions)| (score, ix, positions))
+                }),
+        );
+        //Equal scores keep the order in which the items were found
+        self.matches
+            .sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    }
+
+    fn move_(&mut self, delta: isize) {
+        let last = self.matches.len().saturating_sub(1) as isize;
+        self.cursor = (self.cursor as isize + delta).max(0).min(last) as usize;
+    }
+}
+
+//Matches each whitespace-separated term of `query` as a subsequence of `text`.
+//Terms are case-insensitive, unless they contain an uppercase character.
+//Returns the score and the positions of the matched characters, consecutive characters and word starts score higher.
+fn fuzzy_match_(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|ch| ch.to_ascii_lowercase()).collect();
+    let mut score = 0;
+    let mut positions = vec![];
+    for term in query.split_whitespace() {
+        let case_sensitive = term.chars().any(|ch| ch.is_uppercase());
+        let haystack = if case_sensitive { &chars } else { &lower };
+        let needle: Vec<char> = term.chars().map(|ch| ch.to_ascii_lowercase()).collect();
+        let needle = if case_sensitive {
+            term.chars().collect()
+        } else {
+            needle
+        };
+
+        //Each occurrence of the first character is tried as start, the rest is matched greedily
+        let mut best_opt: Option<(i64, Vec<usize>)> = None;
+        for start in (0..haystack.len()).filter(|&ix| haystack[ix] == needle[0]) {
+            let mut term_positions = vec![start];
+            let mut ix = start + 1;
+            for ch in &needle[1..] {
+                match (ix..haystack.len()).find(|&ix| haystack[ix] == *ch) {
+                    None => break,
+                    Some(found) => {
+                        term_positions.push(found);
+                        ix = found + 1;
+                    }
+                }
+            }
+            if term_positions.len() < needle.len() {
+                break;
+            }
+            let term_score = score_(&chars, &term_positions);
+            if best_opt.as_ref().is_none_or(|(best, _)| term_score > *best) {
+                best_opt = Some((term_score, term_positions));
+            }
+        }
+        let (term_score, term_positions) = best_opt?;
+        score += term_score;
+        positions.extend(term_positions);
+    }
+    positions.sort_unstable();
+    positions.dedup();
+    Some((score, positions))
+}
+
+fn score_(chars: &[char], positions: &[usize]) -> i64 {
+    let mut score = 0;
+    for (ix, &pos) in positions.iter().enumerate() {
+        score += 16;
+        let word_start = pos == 0
+            || !chars[pos - 1].is_alphanumeric()
+            || (chars[pos - 1].is_lowercase() && chars[pos].is_uppercase());
+        if word_start {
+            score += 8;
+        }
+        if ix > 0 {
+            match pos - positions[ix - 1] - 1 {
+                0 => score += 8,
+                gap => score -= gap.min(16) as i64,
+            }
+        }
+    }
+    score
+}
+
+fn draw_(
+    term: &mut Terminal,
+    found: &Found,
+    state: &mut State,
+    options: &Options,
+    file_data: &file::Data,
+) -> Result<()> {
+    let theme = &file_data.theme;
+    let (cols, rows) = term.size()?;
+    state.height = rows.saturating_sub(1).max(1);
+    if state.cursor < state.offset {
+        state.offset = state.cursor;
+    } else if state.cursor >= state.offset + state.height {
+        state.offset = state.cursor + 1 - state.height;
+    }
+    //The preview is shown next to the list, if there is room for it
+    let list_width = if cols >= 60 { cols / 2 } else { cols };
+
+    queue!(term, Clear(ClearType::All))?;
+    for row in 0..state.height {
+        let ix = state.offset + row;
+        if let Some((_, item_ix, positions)) = state.matches.get(ix) {
+            let cursor = if ix == state.cursor { ">" } else { " " };
+            let selected = if state.selected.contains(item_ix) {
+                "*"
+            } else {
+                " "
+            };
+            let text = render_(
+                &found.items[*item_ix].text,
+                positions,
+                list_width.saturating_sub(2),
+                &theme.match_,
+            );
+            let line = format!("{}{}{}", theme.path.paint(cursor), selected, text);
+            queue!(term, cursor::MoveTo(0, row as u16), Print(line))?;
+        }
+    }
+
+    if list_width < cols {
+        if let Some((_, item_ix, _)) = state.matches.get(state.cursor) {
+            let width = cols - list_width - 2;
+            let item = &found.items[*item_ix];
+            let lines = preview_(item, found, state.height, width, options, file_data);
+            for (row, line) in lines.iter().enumerate() {
+                let separator = theme.separator.paint("|");
+                let col = list_width as u16;
+                queue!(
+                    term,
+                    cursor::MoveTo(col, row as u16),
+                    Print(separator),
+                    Print(" "),
+                    Print(line)
+                )?;
+            }
+        }
+    }
+
+    let prompt = format!(
+        "{} {}  {}/{}{}",
+        theme.path.paint(">"),
+        state.query,
+        state.matches.len(),
+        found.items.len(),
+        if found.searching { " searching..." } else { "" }
+    );
+    queue!(term, cursor::MoveTo(0, state.height as u16), Print(prompt))?;
+    term.flush()?;
+    Ok(())
+}
+
+//Returns the preview for `item`: a folder shows its entries, a file that was searched shows its output as mo prints it,
+//starting a bit above the location. Other files show their first lines.
+fn preview_(
+    item: &Item,
+    found: &Found,
+    height: usize,
+    width: usize,
+    options: &Options,
+    file_data: &file::Data,
+) -> Vec<String> {
+    let theme = &file_data.theme;
+    let path = &item.location.path;
+    let (recording, search) = match (item.recording_opt, &file_data.search_opt) {
+        (Some(ix), Some(search)) => (&found.recordings[ix], search),
+        _ => {
+            let mut lines: Vec<String> = if path.is_dir() {
+                let mut names: Vec<String> = std::fs::read_dir(path)
+                    .map(|entries| {
+                        entries
+                            .filter_map(|entry| entry.ok())
+                            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                names.sort();
+                names
+            } else {
+                std::fs::File::open(path)
+                    .map(|file| {
+                        std::io::BufReader::new(file)
+                            .split(b'\n')
+                            .take(height)
+                            .filter_map(|line| line.ok())
+                            .map(|line| display_text(&line).into_owned())
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            };
+            lines.truncate(height);
+            return lines
+                .iter()
+                .map(|line| render_(&printable_(line), &[], width, &theme.match_))
+                .collect();
+        }
+    };
+
+    let mut lines = vec![];
+    let mut location_row = 0;
+    for event in recording.events.iter() {
+        match event {
+            file::Recorded::Header | file::Recorded::Filename => lines.push(
+                theme
+                    .path
+                    .paint(&recording.path.display().to_string())
+                    .to_string(),
+            ),
+            file::Recorded::Separator => lines.push(theme.separator.paint("...").to_string()),
+            file::Recorded::Footer => {}
+            file::Recorded::Line(line, content) => {
+                if item.is_match && line.nr == item.location.line {
+                    location_row = lines.len();
+                }
+                //The line number and its colon take room as well
+                let max_columns = width.saturating_sub(line.nr.to_string().len() + 1).max(1);
+                let mut output = String::new();
+                line.write_colored(
+                    &mut output,
+                    content,
+                    search,
+                    &file_data.replace_opt,
+                    theme,
+                    max_columns,
+                    options.match_window_opt,
+                );
+                lines.extend(output.lines().map(|line| {
+                    //Colors are kept, other control characters would disturb the layout
+                    line.chars()
+                        .map(|ch| {
+                            if ch.is_control() && ch != '\x1b' {
+                                ' '
+                            } else {
+                                ch
+                            }
+                        })
+                        .collect::<String>()
+                }));
+            }
+        }
+    }
+    //The location is shown in the upper part of the preview
+    let first = location_row.saturating_sub(height / 3);
+    lines.into_iter().skip(first).take(height).collect()
+}
+
+//Replaces control characters, they would disturb the layout
+fn printable_(text: &str) -> String {
+    text.chars()
+        .map(|ch| if ch.is_control() { ' ' } else { ch })
+        .collect()
+}
+
+//Cuts `text` after `width` characters and paints the characters at `positions` with `style`
+fn render_(text: &str, positions: &[usize], width: usize, style: &Style) -> String {
+    let mut rendered = String::new();
+    for (pos, ch) in text.chars().take(width).enumerate() {
+        if positions.binary_search(&pos).is_ok() {
+            rendered.push_str(&style.paint(&ch.to_string()).to_string());
+        } else {
+            rendered.push(ch);
+        }
+    }
+    rendered
+}
+
+#[test]
+fn test_fuzzy_match() {
+    assert_eq!(fuzzy_match_("", "src/lib.rs"), Some((0, vec![])));
+    assert_eq!(fuzzy_match_("slr", "src/lib.rs").unwrap().1, vec![0, 4, 8]);
+    assert_eq!(
+        fuzzy_match_("rs lib", "src/lib.rs").unwrap().1,
+        vec![4, 5, 6, 8, 9]
+    );
+    assert_eq!(fuzzy_match_("xyz", "src/lib.rs"), None);
+    assert_eq!(fuzzy_match_("lib rsx", "src/lib.rs"), None);
+    //Uppercase in a term makes it case-sensitive
+    assert!(fuzzy_match_("readme", "README.md").is_some());
+    assert!(fuzzy_match_("Readme", "README.md").is_none());
+
+    //Consecutive characters and word starts are preferred
+    let score = |text| fuzzy_match_("lib", text).unwrap().0;
+    assert!(score("src/lib.rs") > score("src/line_ib.rs"));
+    assert!(score("src/lib.rs") > score("src/glib.rs"));
+}
@@ -1,5 +1,8 @@
+use crate::template::{self, Part};
 use crate::util::{Result, MyError};
 use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::ast::parse::Parser;
+use regex_syntax::ast::{visit, Ast, AssertionKind, Flag, FlagsItemKind, GroupKind, Visitor};
 
 pub fn create_regex(pattern: &str, word_boundary: bool, case_sensitive: bool) -> Result<Regex> {
     let mut pattern = pattern.to_string();
@@ -17,6 +20,10 @@ pub fn create_regex(pattern: &str, word_boundary: bool, case_sensitive: bool) ->
 
 pub struct Search {
     pub regex: Regex,
+    //Finds candidate matches in a complete buffer instead of per line.
+    //`^` and `$` match at line boundaries, hence each line that matches `regex` has a candidate match as well.
+    //None when this does not hold, eg, for patterns that use `\A`, `\z` or that clear the `m` or `R` flag.
+    pub buffer_regex: Option<Regex>,
 }
 impl Search {
     pub fn new(pattern: &str, word_boundary: bool, case_sensitive: bool) -> Result<Search> {
@@ -30,14 +37,64 @@ impl Search {
             Ok(regex) => regex,
         };
 
+        let buffer_regex = if depends_on_text_bounds_(&pattern) {
+            None
+        } else {
+            RegexBuilder::new(&pattern).case_insensitive(!case_sensitive).multi_line(true).crlf(true).build().ok()
+        };
+
         let search = Search {
             regex,
+            buffer_regex,
         };
 
         Ok(search)
     }
 }
 
+//Checks if `^`, `$`, `\A` or `\z` in pattern would match differently in a complete buffer than in a single line.
+//This is the case for `\A` and `\z`, and for `^` and `$` when the multi-line or CRLF flag is cleared, eg, `(?i-m)`.
+fn depends_on_text_bounds_(pattern: &str) -> bool {
+    struct Finder;
+    impl Visitor for Finder {
+        type Output = ();
+        type Err = ();
+        fn finish(self) -> std::result::Result<(), ()> {
+            Ok(())
+        }
+        fn visit_pre(&mut self, ast: &Ast) -> std::result::Result<(), ()> {
+            let flags = match ast {
+                Ast::Assertion(assertion) => match assertion.kind {
+                    AssertionKind::StartText | AssertionKind::EndText => return Err(()),
+                    _ => return Ok(()),
+                },
+                Ast::Flags(set_flags) => &set_flags.flags,
+                Ast::Group(group) => match &group.kind {
+                    GroupKind::NonCapturing(flags) => flags,
+                    _ => return Ok(()),
+                },
+                _ => return Ok(()),
+            };
+            //Flags after the `-` are cleared
+            let mut negated = false;
+            for item in flags.items.iter() {
+                match item.kind {
+                    FlagsItemKind::Negation => negated = true,
+                    FlagsItemKind::Flag(Flag::MultiLine) | FlagsItemKind::Flag(Flag::CRLF) if negated => return Err(()),
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+    }
+
+    match Parser::new().parse(pattern) {
+        Err(_) => true,
+        Ok(ast) => visit(&ast, Finder).is_err(),
+    }
+}
+
+#[derive(Clone)]
 pub struct Replace {
     pub repl: String,
     pub prefix: std::option::Option<String>,
@@ -101,11 +158,119 @@ impl Replace {
                         },
                     };
 
-                    println!("{}: {}", capture_ix, part);
                     res.parts.push((capture_ix, part));
                 }
             },
         }
         res
     }
-}
\ No newline at end of file
+}
+//Capture group, referred to by index or by name
+#[derive(Debug, PartialEq)]
+pub enum Group {
+    Index(usize),
+    Name(String),
+}
+impl Group {
+    pub fn new(s: &str, regex: &Regex) -> Result<Group> {
+        let group = match s.parse::<usize>() {
+            Ok(ix) => {
+                if ix >= regex.captures_len() {
+                    fail!("Capture group {} is not present in the pattern", ix);
+                }
+                Group::Index(ix)
+            },
+            Err(_) => {
+                if !regex.capture_names().any(|name| name == Some(s)) {
+                    fail!("Capture group '{}' is not present in the pattern", s);
+                }
+                Group::Name(s.to_string())
+            },
+        };
+        Ok(group)
+    }
+
+    pub fn get<'a>(&self, caps: &regex::bytes::Captures<'a>) -> Option<regex::bytes::Match<'a>> {
+        match self {
+            Group::Index(ix) => caps.get(*ix),
+            Group::Name(name) => caps.name(name),
+        }
+    }
+}
+
+//Describes what is output for each match: selected capture groups joined by a separator, or a template with groups
+#[derive(Debug, PartialEq)]
+pub struct Extract {
+    parts: Vec<Part<Group>>,
+}
+impl Extract {
+    pub fn from_groups(groups: &[String], separator: &str, regex: &Regex) -> Result<Extract> {
+        let mut parts = vec![];
+        for (ix, group) in groups.iter().enumerate() {
+            if ix > 0 {
+                parts.push(Part::Text(separator.to_string()));
+            }
+            parts.push(Part::Field(Group::new(group, regex)?));
+        }
+        Ok(Extract { parts })
+    }
+
+    //Groups are referred to as `{1}` or `{name}`, use `{{` and `}}` for literal braces
+    pub fn from_template(template: &str, regex: &Regex) -> Result<Extract> {
+        let parts = template::parse(template, |name| Group::new(name, regex))?;
+        Ok(Extract { parts })
+    }
+
+    //Appends the output for a single match to `output`, groups that did not participate are empty
+    pub fn expand(&self, caps: &regex::bytes::Captures, output: &mut Vec<u8>) {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => output.extend_from_slice(text.as_bytes()),
+                Part::Field(group) => {
+                    if let Some(m) = group.get(caps) {
+                        output.extend_from_slice(m.as_bytes());
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[test]
+fn test_extract() -> Result<()> {
+    let search = Search::new(r"(?P<key>\w+)=(\w+)?", false, true)?;
+    let caps = search.regex.captures(b"abc=def").unwrap();
+    let no_value = search.regex.captures(b"abc=").unwrap();
+
+    let scns = [
+        (Extract::from_groups(&["2".to_string()], "\t", &search.regex)?, "def", ""),
+        (Extract::from_groups(&["key".to_string(), "2".to_string()], "\t", &search.regex)?, "abc\tdef", "abc\t"),
+        (Extract::from_template("{key}: {{{2}}}", &search.regex)?, "abc: {def}", "abc: {}"),
+    ];
+    for (extract, expected, expected_no_value) in scns.iter() {
+        let mut output = vec![];
+        extract.expand(&caps, &mut output);
+        assert_eq!(output, expected.as_bytes());
+        output.clear();
+        extract.expand(&no_value, &mut output);
+        assert_eq!(output, expected_no_value.as_bytes());
+    }
+
+    assert!(Extract::from_groups(&["3".to_string()], "\t", &search.regex).is_err());
+    assert!(Extract::from_groups(&["value".to_string()], "\t", &search.regex).is_err());
+    assert!(Extract::from_template("{key", &search.regex).is_err());
+    assert!(Extract::from_template("key}", &search.regex).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_buffer_regex() -> Result<()> {
+    for pattern in ["^foo$", "(?i)^foo", "(?m-i)^foo", "(?-i:foo)$", "a\\b"].iter() {
+        assert!(Search::new(pattern, false, true)?.buffer_regex.is_some(), "{}", pattern);
+    }
+    for pattern in ["\\Afoo", "foo\\z", "(?-m)^foo", "(?i-m)^foo", "(?i-m:^foo)", "(?-R)foo$", "a|(?-m)b"].iter() {
+        assert!(Search::new(pattern, false, true)?.buffer_regex.is_none(), "{}", pattern);
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use crate::search::Group;
+use crate::util::{MyError, Result};
+use regex::bytes::{Captures, Regex};
+
+//Literal text or a field within braces
+#[derive(Debug, PartialEq)]
+pub enum Part<T> {
+    Text(String),
+    Field(T),
+}
+
+//Splits `template` into literal text and fields like `{name}`, `{{` and `}}` are literal braces.
+//`field` converts the content between the braces.
+pub fn parse<T, F>(template: &str, mut field: F) -> Result<Vec<Part<T>>>
+where
+    F: FnMut(&str) -> Result<T>,
+{
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for ch in chars.by_ref() {
+                    if ch == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(ch);
+                }
+                if !closed {
+                    fail!("Template '{}' has an unclosed '{{'", template);
+                }
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(Part::Field(field(&name)?));
+            }
+            '}' => fail!("Template '{}' has an unmatched '}}'", template),
+            _ => text.push(ch),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    Ok(parts)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Field {
+    Path,
+    Line,
+    //1-based byte column of the match
+    Col,
+    Match,
+    Group(Group),
+    Text,
+    Replacement,
+}
+
+//User-defined layout of an output line, produced per match or per line
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part<Field>>,
+    pub per_match: bool,
+}
+
+//Values for a single output line, match related values are None for lines without match
+pub struct Values<'a> {
+    pub path: &'a str,
+    pub nr: u64,
+    pub text: &'a [u8],
+    pub col_opt: Option<usize>,
+    pub match_opt: Option<&'a [u8]>,
+    pub caps_opt: Option<&'a Captures<'a>>,
+    pub replacement_opt: Option<&'a [u8]>,
+}
+
+impl Template {
+    pub fn new(
+        template: &str,
+        per_match: bool,
+        regex: &Regex,
+        has_replace: bool,
+    ) -> Result<Template> {
+        let parts = parse(template, |name| {
+            let field = match name {
+                "path" => Field::Path,
+                "line" => Field::Line,
+                "col" => Field::Col,
+                "match" => Field::Match,
+                "text" => Field::Text,
+                "replacement" if !has_replace => {
+                    fail!("Template field {{replacement}} requires a replacement STRING")
+                }
+                "replacement" => Field::Replacement,
+                _ => match name.strip_prefix("group:") {
+                    Some(group) => Field::Group(Group::new(group, regex)?),
+                    None => fail!("Unknown template field '{{{}}}'", name),
+                },
+            };
+            Ok(field)
+        })?;
+        Ok(Template { parts, per_match })
+    }
+
+    pub fn expand(&self, values: &Values, output: &mut Vec<u8>) {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => output.extend_from_slice(text.as_bytes()),
+                Part::Field(field) => match field {
+                    Field::Path => output.extend_from_slice(values.path.as_bytes()),
+                    Field::Line => output.extend_from_slice(values.nr.to_string().as_bytes()),
+                    Field::Col => {
+                        if let Some(col) = values.col_opt {
+                            output.extend_from_slice(col.to_string().as_bytes());
+                        }
+                    }
+                    Field::Match => output.extend_from_slice(values.match_opt.unwrap_or(b"")),
+                    Field::Group(group) => {
+                        if let Some(m) = values.caps_opt.and_then(|caps| group.get(caps)) {
+                            output.extend_from_slice(m.as_bytes());
+                        }
+                    }
+                    Field::Text => output.extend_from_slice(values.text),
+                    Field::Replacement => {
+                        output.extend_from_slice(values.replacement_opt.unwrap_or(b""))
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[test]
+fn test_template() -> Result<()> {
+    let regex = Regex::new(r"(?P<key>\w+)=(\w+)")?;
+    let caps = regex.captures(b"a key=value").unwrap();
+    let values = Values {
+        path: "dir/file.txt",
+        nr: 12,
+        text: b"a key=value",
+        col_opt: Some(3),
+        match_opt: Some(b"key=value"),
+        caps_opt: Some(&caps),
+        replacement_opt: Some(b"REPL"),
+    };
+
+    let scns = [
+        (
+            "{path}:{line}:{col}: {match}",
+            "dir/file.txt:12:3: key=value",
+        ),
+        (
+            "{group:key},{group:2},\"{text}\"",
+            "key,value,\"a key=value\"",
+        ),
+        ("{{{replacement}}}", "{REPL}"),
+    ];
+    for (template, expected) in scns.iter() {
+        let mut output = vec![];
+        Template::new(template, true, &regex, true)?.expand(&values, &mut output);
+        assert_eq!(output, expected.as_bytes());
+    }
+
+    assert!(Template::new("{replacement}", true, &regex, false).is_err());
+    assert!(Template::new("{group:3}", true, &regex, false).is_err());
+    assert!(Template::new("{unknown}", true, &regex, false).is_err());
+    assert!(Template::new("{path", true, &regex, false).is_err());
+
+    Ok(())
+}
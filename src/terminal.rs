@@ -0,0 +1,67 @@
+use crate::cli::Options;
+use crate::util::{MyError, Result};
+use crossterm::{cursor, execute, terminal};
+use std::io::Write;
+
+//Full-screen access to the terminal via /dev/tty, hence Stdout can still be redirected.
+//The terminal is restored when this is dropped.
+pub struct Terminal {
+    tty: std::fs::File,
+    color_output: Option<bool>,
+}
+
+impl Terminal {
+    pub fn open(options: &Options) -> Result<Terminal> {
+        let tty = match std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+            Err(err) => fail!("Could not open the terminal: {}", err),
+            Ok(tty) => tty,
+        };
+        let mut term = Terminal {
+            tty,
+            color_output: options.color_output,
+        };
+        //Colors are determined by the terminal we draw on, not by Stdout
+        colored::control::set_override(true);
+        terminal::enable_raw_mode()?;
+        //Lines that are too long are clipped at the right edge instead of wrapped
+        execute!(
+            term.tty,
+            terminal::EnterAlternateScreen,
+            terminal::DisableLineWrap,
+            cursor::Hide
+        )?;
+        Ok(term)
+    }
+
+    //Number of columns and rows
+    pub fn size(&self) -> Result<(usize, usize)> {
+        let (cols, rows) = terminal::size()?;
+        Ok((cols as usize, rows as usize))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(
+            self.tty,
+            cursor::Show,
+            terminal::EnableLineWrap,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+        match self.color_output {
+            Some(b) => colored::control::set_override(b),
+            None => colored::control::unset_override(),
+        }
+    }
+}
+
+impl Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tty.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.tty.flush()
+    }
+}
@@ -0,0 +1,176 @@
+use crate::util::{MyError, Result};
+use colored::{Color, ColoredString, Colorize};
+
+//Environment variables to select a theme and to override individual elements
+pub const THEME_ENV: &str = "MO_THEME";
+pub const COLORS_ENV: &str = "MO_COLORS";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    reversed: bool,
+}
+
+impl Style {
+    //Parses a '+'-separated list of colors, `on_COLOR` backgrounds and attributes, eg, `black+on_yellow+bold`
+    pub fn new(spec: &str) -> Result<Style> {
+        let mut style = Style::default();
+        for word in spec
+            .split('+')
+            .filter(|word| !word.is_empty() && *word != "none")
+        {
+            match word {
+                "bold" => style.bold = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "reversed" => style.reversed = true,
+                _ => match word.strip_prefix("on_") {
+                    Some(bg) => style.bg = Some(color_(bg)?),
+                    None => style.fg = Some(color_(word)?),
+                },
+            }
+        }
+        Ok(style)
+    }
+
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut s = text.normal();
+        if let Some(fg) = self.fg {
+            s = s.color(fg);
+        }
+        if let Some(bg) = self.bg {
+            s = s.on_color(bg);
+        }
+        if self.bold {
+            s = s.bold();
+        }
+        if self.italic {
+            s = s.italic();
+        }
+        if self.underline {
+            s = s.underline();
+        }
+        if self.reversed {
+            s = s.reversed();
+        }
+        s
+    }
+}
+
+fn color_(name: &str) -> Result<Color> {
+    match name.replace('_', " ").parse() {
+        Err(_) => fail!("Unknown color '{}'", name),
+        Ok(color) => Ok(color),
+    }
+}
+
+//Styles for each element of the output
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Theme {
+    pub path: Style,
+    pub line: Style,
+    pub match_: Style,
+    pub replacement: Style,
+    pub context: Style,
+    pub separator: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::preset("dark").unwrap()
+    }
+}
+
+impl Theme {
+    pub fn preset(name: &str) -> Result<Theme> {
+        let specs = match name {
+            "dark" => [
+                "green+bold",
+                "yellow",
+                "bright_cyan+bold",
+                "on_purple",
+                "",
+                "",
+            ],
+            "light" => [
+                "blue+bold",
+                "magenta",
+                "red+bold",
+                "black+on_bright_green",
+                "",
+                "",
+            ],
+            "high-contrast" => [
+                "bright_white+on_blue+bold",
+                "bright_yellow+bold",
+                "black+on_bright_yellow+bold",
+                "black+on_bright_green+bold",
+                "",
+                "bright_white+bold",
+            ],
+            "monochrome-bold" => ["bold+underline", "", "bold", "reversed", "", ""],
+            _ => fail!(
+                "Unknown theme '{}', use dark, light, high-contrast or monochrome-bold",
+                name
+            ),
+        };
+        Ok(Theme {
+            path: Style::new(specs[0])?,
+            line: Style::new(specs[1])?,
+            match_: Style::new(specs[2])?,
+            replacement: Style::new(specs[3])?,
+            context: Style::new(specs[4])?,
+            separator: Style::new(specs[5])?,
+        })
+    }
+
+    //Applies ':'-separated overrides like `path=blue+bold:match=black+on_yellow`
+    pub fn apply(&mut self, overrides: &str) -> Result<()> {
+        for item in overrides.split(':').filter(|item| !item.is_empty()) {
+            let (element, spec) = match item.split_once('=') {
+                None => fail!("Expected ELEMENT=STYLE instead of '{}'", item),
+                Some(pair) => pair,
+            };
+            let style = Style::new(spec)?;
+            match element {
+                "path" => self.path = style,
+                "line" => self.line = style,
+                "match" => self.match_ = style,
+                "replacement" => self.replacement = style,
+                "context" => self.context = style,
+                "separator" => self.separator = style,
+                _ => fail!("Unknown element '{}', use path, line, match, replacement, context or separator", element),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_theme() -> Result<()> {
+    let mut theme = Theme::preset("light")?;
+    theme.apply("path=bright_blue:match=black+on_yellow+bold:context=")?;
+    assert_eq!(theme.path, Style::new("bright_blue")?);
+    assert_eq!(
+        theme.match_,
+        Style {
+            fg: Some(Color::Black),
+            bg: Some(Color::Yellow),
+            bold: true,
+            ..Style::default()
+        }
+    );
+    assert_eq!(theme.context, Style::default());
+    assert_eq!(theme.line, Theme::preset("light")?.line);
+
+    assert!(Theme::preset("solarized").is_err());
+    assert!(theme.apply("path").is_err());
+    assert!(theme.apply("header=bold").is_err());
+    assert!(theme.apply("match=blurple").is_err());
+
+    Ok(())
+}
@@ -0,0 +1,540 @@
+use crate::cli::Options;
+use crate::file;
+use crate::line::Line;
+use crate::search::{self, Replace, Search};
+use crate::terminal::Terminal;
+use crate::theme::{Style, Theme};
+use crate::util::{Range, Result};
+use colored::Colorize;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::{cursor, queue};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+//Searching stops at this number of matches, to keep the results responsive
+const MAX_MATCHES: usize = 10_000;
+
+//Input fields, focus moves from these fields to the results
+const FIELD_NAMES: [&str; 3] = ["Search", "Replace", "Files"];
+const RESULTS_FOCUS: usize = FIELD_NAMES.len();
+
+const HELP: &str = "Tab: next field  Space: toggle  Ctrl-T: toggle all  Ctrl-R: replace  Esc: quit";
+
+//Match that is found while typing, `range` is relative to the line text
+struct Match {
+    nr: u64,
+    range: Range,
+    text: Vec<u8>,
+    enabled: bool,
+}
+
+struct FileResult {
+    path: PathBuf,
+    matches: Vec<Match>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Row {
+    File(usize),
+    Match(usize, usize),
+}
+
+struct State {
+    fields: [String; 3],
+    focus: usize,
+    results: Vec<FileResult>,
+    rows: Vec<Row>,
+    cursor: usize,
+    //First row that is shown
+    offset: usize,
+    //Indicates that the fields changed since the last search
+    dirty: bool,
+    status: String,
+    //Built from the Replace field whenever it changes
+    replace_opt: Option<Replace>,
+}
+
+impl State {
+    fn new(options: &Options) -> State {
+        State {
+            fields: [
+                options.search_pattern_opt.clone().unwrap_or_default(),
+                options.replace_opt.clone().unwrap_or_default(),
+                String::new(),
+            ],
+            focus: 0,
+            results: vec![],
+            rows: vec![],
+            cursor: 0,
+            offset: 0,
+            dirty: true,
+            status: String::new(),
+            replace_opt: Some(Replace::new(
+                options.replace_opt.as_deref().unwrap_or_default(),
+                &options.capture_group_prefix_opt,
+            )),
+        }
+    }
+
+    fn set_results(&mut self, results: Vec<FileResult>) {
+        self.rows.clear();
+        for (fix, file) in results.iter().enumerate() {
+            self.rows.push(Row::File(fix));
+            self.rows
+                .extend((0..file.matches.len()).map(|mix| Row::Match(fix, mix)));
+        }
+        self.results = results;
+        self.cursor = self.cursor.min(self.rows.len().saturating_sub(1));
+        self.offset = self.offset.min(self.cursor);
+    }
+
+    fn move_(&mut self, delta: isize) {
+        let last = self.rows.len().saturating_sub(1) as isize;
+        self.cursor = (self.cursor as isize + delta).max(0).min(last) as usize;
+    }
+
+    //Toggles the match under the cursor, or all matches of the file under the cursor
+    fn toggle(&mut self) {
+        match self.rows.get(self.cursor) {
+            None => {}
+            Some(Row::File(fix)) => {
+                let matches = &mut self.results[*fix].matches;
+                let enable = !matches.iter().all(|m| m.enabled);
+                matches.iter_mut().for_each(|m| m.enabled = enable);
+            }
+            Some(Row::Match(fix, mix)) => {
+                let m = &mut self.results[*fix].matches[*mix];
+                m.enabled = !m.enabled;
+            }
+        }
+    }
+
+    fn toggle_all(&mut self) {
+        let enable = !self.all_matches_().all(|m| m.enabled);
+        for file in self.results.iter_mut() {
+            file.matches.iter_mut().for_each(|m| m.enabled = enable);
+        }
+    }
+
+    //Number of matches, of files and of enabled matches
+    fn counts(&self) -> (usize, usize, usize) {
+        let enabled = self.all_matches_().filter(|m| m.enabled).count();
+        (self.all_matches_().count(), self.results.len(), enabled)
+    }
+
+    fn all_matches_(&self) -> impl Iterator<Item = &Match> {
+        self.results.iter().flat_map(|file| file.matches.iter())
+    }
+}
+
+//Full-screen search and replace: matches are searched while typing and replaced for the enabled matches only
+pub fn run(options: &Options, file_data: &mut file::Data) -> Result<()> {
+    let paths = crate::scan_paths_(options, file_data)?;
+    //Content is written back, it should not be backed by the file itself
+    file_data.mmap_mode = crate::cli::MmapMode::Never;
+
+    let mut state = State::new(options);
+    let mut term = Terminal::open(options)?;
+    loop {
+        //Searching is postponed while keys are pending
+        if state.dirty && !event::poll(Duration::from_millis(100))? {
+            search_(&paths, options, file_data, &mut state);
+        }
+        draw_(&mut term, &state, options, file_data, &file_data.theme)?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if ctrl => return Ok(()),
+            KeyCode::Tab => state.focus = (state.focus + 1) % (RESULTS_FOCUS + 1),
+            KeyCode::BackTab => state.focus = (state.focus + RESULTS_FOCUS) % (RESULTS_FOCUS + 1),
+            KeyCode::Char('t') if ctrl => state.toggle_all(),
+            KeyCode::Char('r') if ctrl => {
+                if state.dirty {
+                    search_(&paths, options, file_data, &mut state);
+                }
+                let status = apply_(options, file_data, &state);
+                search_(&paths, options, file_data, &mut state);
+                state.status = status;
+            }
+            _ if state.focus < RESULTS_FOCUS => {
+                let field = &mut state.fields[state.focus];
+                match key.code {
+                    KeyCode::Enter => state.focus = RESULTS_FOCUS,
+                    KeyCode::Backspace => {
+                        field.pop();
+                        state.dirty = true;
+                    }
+                    KeyCode::Char('u') if ctrl => {
+                        field.clear();
+                        state.dirty = true;
+                    }
+                    KeyCode::Char(ch) if !ctrl => {
+                        field.push(ch);
+                        state.dirty = true;
+                    }
+                    _ => {}
+                }
+                if state.focus == 1 {
+                    state.replace_opt = Some(Replace::new(
+                        &state.fields[1],
+                        &options.capture_group_prefix_opt,
+                    ));
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => state.move_(-1),
+            KeyCode::Down | KeyCode::Char('j') => state.move_(1),
+            KeyCode::PageUp => state.move_(-10),
+            KeyCode::PageDown => state.move_(10),
+            KeyCode::Char(' ') | KeyCode::Enter => state.toggle(),
+            _ => {}
+        }
+    }
+}
+
+fn search_(paths: &[PathBuf], options: &Options, file_data: &mut file::Data, state: &mut State) {
+    state.dirty = false;
+    state.status.clear();
+    state.set_results(vec![]);
+    if state.fields[0].is_empty() {
+        return;
+    }
+    let search = Search::new(
+        &state.fields[0],
+        options.word_boundary,
+        options.case_sensitive,
+    );
+    let filter = match state.fields[2].as_str() {
+        "" => Ok(None),
+        filter => search::create_regex(filter, false, false).map(Some),
+    };
+    let (search, filter_opt) = match (search, filter) {
+        (Ok(search), Ok(filter_opt)) => (search, filter_opt),
+        (Err(err), _) | (_, Err(err)) => {
+            state.status = err.to_string();
+            return;
+        }
+    };
+    file_data.search_opt = Some(search);
+
+    let mut results = vec![];
+    let mut count = 0;
+    for path in paths {
+        let path = path.strip_prefix(".").unwrap_or(path);
+        if let Some(filter) = &filter_opt {
+            if !filter.is_match(path.display().to_string().as_bytes()) {
+                continue;
+            }
+        }
+        if file_data.load(path).is_err() || !file_data.search_for_matches().unwrap_or(false) {
+            continue;
+        }
+        let content = file_data.content();
+        let mut matches = vec![];
+        for line in file_data.lines.iter() {
+            for r in line.matches.iter() {
+                matches.push(Match {
+                    nr: line.nr,
+                    range: r.clone(),
+                    text: line.as_text_slice(content).to_vec(),
+                    enabled: true,
+                });
+            }
+        }
+        if matches.is_empty() {
+            continue;
+        }
+        count += matches.len();
+        results.push(FileResult {
+            path: path.to_path_buf(),
+            matches,
+        });
+        if count >= MAX_MATCHES {
+            state.status = format!("Search stopped at {} matches", count);
+            break;
+        }
+    }
+    state.set_results(results);
+}
+
+//Replaces the enabled matches, files that changed since searching are only replaced where the matches are still the same.
+//With --simulate, nothing is written.
+fn apply_(options: &Options, file_data: &mut file::Data, state: &State) -> String {
+    file_data.replace_opt = state.replace_opt.clone();
+    let (mut match_count, mut file_count) = (0, 0);
+    let mut errors = vec![];
+    for file in state.results.iter() {
+        let enabled: Vec<(u64, Range)> = file
+            .matches
+            .iter()
+            .filter(|m| m.enabled)
+            .map(|m| (m.nr, m.range.clone()))
+            .collect();
+        if enabled.is_empty() {
+            continue;
+        }
+        let replace_res = file_data
+            .load(&file.path)
+            .and_then(|_| file_data.search_for_matches())
+            .and_then(|_| file_data.complete_lines());
+        if let Err(err) = replace_res {
+            errors.push(format!("{}: {}", file.path.display(), err));
+            continue;
+        }
+        for line in file_data.lines.iter_mut() {
+            let nr = line.nr;
+            line.matches
+                .retain(|r| enabled.iter().any(|(e_nr, e_r)| *e_nr == nr && e_r == r));
+        }
+        let count: usize = file_data.lines.iter().map(|line| line.matches.len()).sum();
+        if count == 0 {
+            continue;
+        }
+        //With --simulate, files are only counted
+        if options.simulate_replace {
+            match_count += count;
+            file_count += 1;
+            continue;
+        }
+        match file_data.replace_and_write() {
+            Err(err) => errors.push(format!("{}: {}", file.path.display(), err)),
+            Ok(()) => {
+                match_count += count;
+                file_count += 1;
+            }
+        }
+    }
+    file_data.replace_opt = None;
+
+    let verb = if options.simulate_replace {
+        "Would replace"
+    } else {
+        "Replaced"
+    };
+    let mut status = format!("{} {} matches in {} files", verb, match_count, file_count);
+    if let Some(error) = errors.first() {
+        status.push_str(&format!(", {} failed: {}", errors.len(), error));
+    }
+    status
+}
+
+fn draw_(
+    term: &mut Terminal,
+    state: &State,
+    options: &Options,
+    file_data: &file::Data,
+    theme: &Theme,
+) -> Result<()> {
+    let (cols, rows) = term.size()?;
+    queue!(term, Clear(ClearType::All))?;
+
+    for (ix, name) in FIELD_NAMES.iter().enumerate() {
+        let label = format!("{:>8}: ", name);
+        let label = if ix == state.focus {
+            theme.path.paint(&label)
+        } else {
+            label.normal()
+        };
+        let text = clip_(&[(&state.fields[ix], None)], cols.saturating_sub(11));
+        let cursor = if ix == state.focus {
+            " ".reversed()
+        } else {
+            " ".normal()
+        };
+        queue!(
+            term,
+            cursor::MoveTo(0, ix as u16),
+            Print(label),
+            Print(text),
+            Print(cursor)
+        )?;
+    }
+
+    let (match_count, file_count, enabled) = state.counts();
+    let summary = format!(
+        "{} of {} matches in {} files selected  {}",
+        enabled, match_count, file_count, state.status
+    );
+    let summary = clip_(&[(&summary, Some(&theme.separator))], cols);
+    queue!(
+        term,
+        cursor::MoveTo(0, RESULTS_FOCUS as u16),
+        Print(summary)
+    )?;
+
+    //Rows with a match take two lines, the original and the replaced line
+    let height = rows.saturating_sub(RESULTS_FOCUS + 2).max(1);
+    let row_height = |row: &Row| if let Row::Match(..) = row { 2 } else { 1 };
+    let mut offset = state.offset.min(state.cursor);
+    while offset < state.cursor
+        && state.rows[offset..=state.cursor]
+            .iter()
+            .map(row_height)
+            .sum::<usize>()
+            > height
+    {
+        offset += 1;
+    }
+
+    let mut lines = vec![];
+    for (ix, row) in state.rows.iter().enumerate().skip(offset) {
+        if lines.len() >= height {
+            break;
+        }
+        let cursor = if ix == state.cursor && state.focus == RESULTS_FOCUS {
+            theme.path.paint(">")
+        } else {
+            " ".normal()
+        };
+        match row {
+            Row::File(fix) => {
+                let file = &state.results[*fix];
+                let enabled = file.matches.iter().filter(|m| m.enabled).count();
+                let mark = match enabled {
+                    0 => "[ ]",
+                    n if n == file.matches.len() => "[x]",
+                    _ => "[-]",
+                };
+                let path = file.path.display().to_string();
+                let text = clip_(&[(&path, Some(&theme.path))], cols.saturating_sub(5));
+                lines.push(format!("{}{} {}", cursor, mark, text));
+            }
+            Row::Match(fix, mix) => {
+                let m = &state.results[*fix].matches[*mix];
+                let mark = if m.enabled { "[x]" } else { "[ ]" };
+                //The line number and its colon take room as well
+                let width = cols.saturating_sub(7 + m.nr.to_string().len() + 1).max(1);
+                if let Some(search) = &file_data.search_opt {
+                    let (before, after) =
+                        render_match_(m, search, &state.replace_opt, width, options, theme);
+                    lines.push(format!("{}  {} {}", cursor, mark, before));
+                    lines.push(format!("      {}", after));
+                }
+            }
+        }
+    }
+    for (row, line) in lines.iter().take(height).enumerate() {
+        let row = (RESULTS_FOCUS + 1 + row) as u16;
+        queue!(term, cursor::MoveTo(0, row), Print(line))?;
+    }
+
+    let help = clip_(&[(HELP, Some(&theme.separator))], cols);
+    queue!(
+        term,
+        cursor::MoveTo(0, rows.saturating_sub(1) as u16),
+        Print(help)
+    )?;
+    term.flush()?;
+    Ok(())
+}
+
+//Returns the line with the match and the line with its replacement, as mo outputs them
+fn render_match_(
+    m: &Match,
+    search: &Search,
+    replace_opt: &Option<Replace>,
+    width: usize,
+    options: &Options,
+    theme: &Theme,
+) -> (String, String) {
+    let mut line = Line::new(m.nr, 0, m.text.len(), 0);
+    line.matches.push(m.range.clone());
+    //Long lines show the text around the match
+    let window = options.match_window_opt.unwrap_or(width / 3);
+    let mut output = String::new();
+    line.write_colored(
+        &mut output,
+        &m.text,
+        search,
+        replace_opt,
+        theme,
+        width,
+        Some(window),
+    );
+    //Colors are kept, other control characters would disturb the layout
+    let mut lines = output.lines().map(|line| {
+        line.chars()
+            .map(|ch| {
+                if ch.is_control() && ch != '\x1b' {
+                    ' '
+                } else {
+                    ch
+                }
+            })
+            .collect::<String>()
+    });
+    let before = lines.next().unwrap_or_default();
+    let after = lines.next().unwrap_or_default();
+    (before, after)
+}
+
+//Concatenates the parts, painted with their style, and cuts them after `width` characters.
+//Control characters are replaced, they would disturb the layout.
+fn clip_(parts: &[(&str, Option<&Style>)], mut width: usize) -> String {
+    let mut clipped = String::new();
+    for (text, style_opt) in parts {
+        let text: String = text
+            .chars()
+            .take(width)
+            .map(|ch| if ch.is_control() { ' ' } else { ch })
+            .collect();
+        width -= text.chars().count();
+        match style_opt {
+            Some(style) if !text.is_empty() => clipped.push_str(&style.paint(&text).to_string()),
+            _ => clipped.push_str(&text),
+        }
+    }
+    clipped
+}
+
+#[test]
+fn test_state() {
+    let file = |path: &str, nrs: &[u64]| FileResult {
+        path: PathBuf::from(path),
+        matches: nrs
+            .iter()
+            .map(|nr| Match {
+                nr: *nr,
+                range: 0..1,
+                text: b"a\tb".to_vec(),
+                enabled: true,
+            })
+            .collect(),
+    };
+    let mut state = State::new(&Options::default());
+    state.set_results(vec![file("a.rs", &[1, 3]), file("b.rs", &[2])]);
+    assert_eq!(
+        state.rows,
+        vec![
+            Row::File(0),
+            Row::Match(0, 0),
+            Row::Match(0, 1),
+            Row::File(1),
+            Row::Match(1, 0)
+        ]
+    );
+    assert_eq!(state.counts(), (3, 2, 3));
+
+    state.move_(1);
+    state.toggle();
+    assert_eq!(state.counts(), (3, 2, 2));
+    //A partially enabled file is enabled completely
+    state.move_(-5);
+    state.toggle();
+    assert_eq!(state.counts(), (3, 2, 3));
+    state.toggle();
+    assert_eq!(state.counts(), (3, 2, 1));
+    state.toggle_all();
+    assert_eq!(state.counts(), (3, 2, 3));
+    state.toggle_all();
+    assert_eq!(state.counts(), (3, 2, 0));
+
+    assert_eq!(clip_(&[("a\tb", None), ("cd", None)], 4), "a bc");
+}
@@ -16,8 +16,7 @@ impl std::fmt::Display for MyError {
 }
 impl MyError {
     pub fn new(descr: &str) -> MyError {
-        let er = MyError{descr: descr.to_string()};
-        er
+        MyError{descr: descr.to_string()}
     }
     pub fn create(descr: &str) -> Error {
         Error::from(MyError::new(descr))
@@ -38,6 +37,52 @@ macro_rules! fail {
 
 pub type Range = std::ops::Range<usize>;
 
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::path::PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    std::path::PathBuf::from(String::from_utf8_lossy(bytes).as_ref())
+}
+
+//Splits a list of filepaths. When a NUL is present, this is used as separator, else newline.
+pub fn split_filepaths(buffer: &[u8]) -> Vec<std::path::PathBuf> {
+    let sep = if buffer.contains(&0x00_u8) { 0x00_u8 } else { 0x0a_u8 };
+    buffer
+        .split(|&ch| ch == sep)
+        .map(|part| match sep {
+            0x0a_u8 => part.strip_suffix(b"\r").unwrap_or(part),
+            _ => part,
+        })
+        .filter(|part| !part.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+//Temporary folder for tests that is also removed when the test fails
+#[cfg(test)]
+pub struct TempDir {
+    pub path: std::path::PathBuf,
+}
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> Result<TempDir> {
+        let path = std::env::temp_dir().join(format!("mo-test-{}-{}", name, std::process::id()));
+        //Leftovers from an aborted run with the same pid are removed first
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path)?;
+        Ok(TempDir { path })
+    }
+}
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
 #[test]
 fn test_create_custom_error() {
     let my_err = MyError::create("My custom error message");
@@ -68,3 +113,18 @@ fn test_fail_macro() {
         assert!(res.is_err())
     }
 }
+
+#[test]
+fn test_split_filepaths() {
+    use std::path::PathBuf;
+
+    assert!(split_filepaths(b"").is_empty());
+    assert_eq!(
+        split_filepaths(b"a.txt\nsub dir/b.txt\r\n\n"),
+        vec![PathBuf::from("a.txt"), PathBuf::from("sub dir/b.txt")]
+    );
+    assert_eq!(
+        split_filepaths(b"a.txt\0with\nnewline.txt\0"),
+        vec![PathBuf::from("a.txt"), PathBuf::from("with\nnewline.txt")]
+    );
+}
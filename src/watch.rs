@@ -0,0 +1,327 @@
+use crate::cli::{Options, OutputOnly};
+use crate::file;
+use crate::folder::Scanner;
+use crate::line::Line;
+use crate::util::{MyError, Result};
+use crate::Printer;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+//Changes that arrive within this period are handled together, editors often write a file in several steps
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+//Matching line, `line` refers to the start of `content`
+struct Hit {
+    line: Line,
+    content: Vec<u8>,
+}
+
+type Results = BTreeMap<PathBuf, Vec<Hit>>;
+
+//Root as passed on the command line, and its canonical path that is used in change events
+struct Root<'a> {
+    path: PathBuf,
+    abs_path: PathBuf,
+    //None when the root is a file
+    scanner_opt: Option<Scanner<'a>>,
+}
+
+impl Root<'_> {
+    //Path of `abs_path` as it is produced by scanning this root, None when it is not below this root
+    fn key(&self, abs_path: &Path) -> Option<PathBuf> {
+        match abs_path.strip_prefix(&self.abs_path) {
+            Err(_) => None,
+            Ok(rel) if rel.as_os_str().is_empty() => Some(self.path.clone()),
+            Ok(rel) => Some(self.path.join(rel)),
+        }
+    }
+
+    //Applies the same ignore rules and filters as scanning, removed files are not included anymore
+    fn includes(&self, key: &Path) -> bool {
+        match &self.scanner_opt {
+            None => key.is_file(),
+            Some(scanner) => matches!(scanner.why_ignored(key), Ok(None)),
+        }
+    }
+}
+
+//Searches the roots and keeps searching the files that change, until interrupted.
+//All results are output again after each change, or only the matches that appeared or disappeared.
+pub fn watch(options: &Options, file_data: &mut file::Data) -> Result<()> {
+    if file_data.search_opt.is_none() {
+        fail!("Watching requires a search PATTERN");
+    }
+    if file_data.replace_opt.is_some() && !options.simulate_replace {
+        fail!("Watching cannot be combined with replacing, use -n to simulate it");
+    }
+    if options.files_from_opt.is_some() {
+        fail!("Watching cannot be combined with --files-from");
+    }
+    //Only the lines with matches are kept to compare them after a change
+    if options.output_before > 0 || options.output_after > 0 {
+        fail!("Watching cannot be combined with context lines via -A or -B");
+    }
+    if options.passthrough {
+        fail!("Watching cannot be combined with --passthrough");
+    }
+    if options.invert_pattern {
+        fail!("Watching cannot be combined with -v");
+    }
+    if options.search_compressed {
+        fail!("Watching cannot be combined with searching compressed files via -z");
+    }
+
+    let roots = if options.roots.is_empty() {
+        vec![".".to_string()]
+    } else {
+        options.roots.clone()
+    };
+    let mut watch_roots = vec![];
+    for root in roots.iter().map(PathBuf::from) {
+        let abs_path = match std::fs::canonicalize(&root) {
+            Err(err) => fail!("Could not watch '{}': {}", root.display(), err),
+            Ok(abs_path) => abs_path,
+        };
+        let scanner_opt = if root.is_dir() {
+            Some(Scanner::new(&root, options)?)
+        } else {
+            None
+        };
+        watch_roots.push(Root {
+            path: root,
+            abs_path,
+            scanner_opt,
+        });
+    }
+
+    //Watching starts before the initial search, changes made during that search are not missed
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for root in watch_roots.iter() {
+        match (&root.scanner_opt, root.abs_path.parent()) {
+            //Editors often replace a file instead of writing it, its folder keeps being watched.
+            //Events for other files in this folder are skipped via Root::key().
+            (None, Some(folder)) => watcher.watch(folder, RecursiveMode::NonRecursive)?,
+            _ => watcher.watch(&root.abs_path, RecursiveMode::Recursive)?,
+        }
+    }
+
+    let mut results = Results::new();
+    for path in crate::scan_paths_(options, file_data)? {
+        let hits = search_file_(&path, file_data);
+        if !hits.is_empty() {
+            results.insert(path, hits);
+        }
+    }
+    print_results_(options, file_data, &results);
+    //NULL-separated output has no newline that flushes it
+    std::io::stdout().flush()?;
+
+    loop {
+        let mut changed = BTreeSet::new();
+        add_changes_(rx.recv()?, &mut changed);
+        while let Ok(event) = rx.recv_timeout(SETTLE_TIME) {
+            add_changes_(event, &mut changed);
+        }
+
+        //Old hits of each file that changed its hits
+        let mut changes = vec![];
+        for abs_path in changed.iter() {
+            let (root, key) = match watch_roots
+                .iter()
+                .find_map(|root| root.key(abs_path).map(|key| (root, key)))
+            {
+                None => continue,
+                Some(root_key) => root_key,
+            };
+            let hits = if root.includes(&key) {
+                search_file_(&key, file_data)
+            } else {
+                vec![]
+            };
+            let old_hits_opt = if hits.is_empty() {
+                results.remove(&key)
+            } else {
+                results.insert(key.clone(), hits)
+            };
+            let old_hits = old_hits_opt.unwrap_or_default();
+            if !same_hits_(&old_hits, results.get(&key).map_or(&[], |hits| hits)) {
+                changes.push((key, old_hits));
+            }
+        }
+
+        if changes.is_empty() {
+            continue;
+        }
+        if options.watch_diff {
+            print_changes_(options, file_data, &results, &changes);
+        } else {
+            print_results_(options, file_data, &results);
+        }
+        std::io::stdout().flush()?;
+    }
+}
+
+fn add_changes_(event: notify::Result<notify::Event>, changed: &mut BTreeSet<PathBuf>) {
+    match event {
+        Err(err) => eprintln!("Error: could not watch for changes: {}", err),
+        //Reading files does not change them
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+        Ok(event) => changed.extend(event.paths),
+    }
+}
+
+//Searches `path` and keeps its matching lines. Files that cannot be loaded, eg, because they were removed, have no hits.
+fn search_file_(path: &Path, file_data: &mut file::Data) -> Vec<Hit> {
+    if file_data.load(path).is_err() || !file_data.search_for_matches().unwrap_or(false) {
+        return vec![];
+    }
+    let content = file_data.content();
+    file_data
+        .lines
+        .iter()
+        .filter(|line| !line.matches.is_empty())
+        .map(|line| {
+            let slice = line.as_slice(content);
+            let mut hit_line = Line::new(line.nr, 0, slice.len(), line.eol_size);
+            hit_line.matches = line.matches.clone();
+            Hit {
+                line: hit_line,
+                content: slice.to_vec(),
+            }
+        })
+        .collect()
+}
+
+fn same_hits_(a: &[Hit], b: &[Hit]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .all(|(a, b)| a.line.nr == b.line.nr && a.content == b.content)
+}
+
+//Splits in the hits that appeared and the ones that disappeared.
+//Hits are compared on their content only, a match that moved to another line did not change.
+fn diff_<'a>(old_hits: &'a [Hit], new_hits: &'a [Hit]) -> (Vec<&'a Hit>, Vec<&'a Hit>) {
+    let mut is_kept = vec![false; old_hits.len()];
+    let mut appeared = vec![];
+    for hit in new_hits.iter() {
+        match (0..old_hits.len()).find(|&ix| !is_kept[ix] && old_hits[ix].content == hit.content) {
+            None => appeared.push(hit),
+            Some(ix) => is_kept[ix] = true,
+        }
+    }
+    let disappeared = old_hits
+        .iter()
+        .zip(is_kept.iter())
+        .filter(|(_, is_kept)| !**is_kept)
+        .map(|(hit, _)| hit)
+        .collect();
+    (appeared, disappeared)
+}
+
+fn create_printer_<'a>(options: &'a Options, file_data: &'a file::Data) -> Printer<'a> {
+    Printer::new(
+        options,
+        file_data.search_opt.as_ref().unwrap(),
+        &file_data.replace_opt,
+        &file_data.extract_opt,
+        &file_data.template_opt,
+        &file_data.theme,
+        &file_data.hyperlink_opt,
+    )
+}
+
+fn display_path_(path: &Path) -> &Path {
+    path.strip_prefix(".").unwrap_or(path)
+}
+
+//Outputs all results, on a clean screen for console output
+fn print_results_(options: &Options, file_data: &file::Data, results: &Results) {
+    let printer = create_printer_(options, file_data);
+    if printer.console_output {
+        print!("\x1b[2J\x1b[H");
+    }
+    for (path, hits) in results.iter() {
+        let path = display_path_(path);
+        if options.output_only == Some(OutputOnly::Filenames) {
+            printer.filename(path);
+            continue;
+        }
+        printer.header(path);
+        for hit in hits.iter() {
+            printer.line(&hit.line, &hit.content, Some(path));
+        }
+        printer.footer();
+    }
+}
+
+//Outputs the lines with matches that appeared, marked with `+`, and those that disappeared, marked with `-`
+fn print_changes_(
+    options: &Options,
+    file_data: &file::Data,
+    results: &Results,
+    changes: &[(PathBuf, Vec<Hit>)],
+) {
+    let printer = create_printer_(options, file_data);
+    for (path, old_hits) in changes.iter() {
+        let new_hits = results.get(path).map_or(&[][..], |hits| hits);
+        let (appeared, disappeared) = diff_(old_hits, new_hits);
+        if appeared.is_empty() && disappeared.is_empty() {
+            continue;
+        }
+        let path = display_path_(path);
+        if options.output_only == Some(OutputOnly::Filenames) {
+            let marker = if new_hits.is_empty() { "-" } else { "+" };
+            print!("{}", file_data.theme.separator.paint(marker));
+            printer.filename(path);
+            continue;
+        }
+        printer.header(path);
+        let marked = disappeared
+            .iter()
+            .map(|hit| ("-", hit))
+            .chain(appeared.iter().map(|hit| ("+", hit)));
+        for (marker, hit) in marked {
+            print!("{}", file_data.theme.separator.paint(marker));
+            printer.line(&hit.line, &hit.content, Some(path));
+        }
+        printer.footer();
+    }
+}
+
+#[test]
+fn test_diff() {
+    let hits = |texts: &[&str]| -> Vec<Hit> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(ix, text)| Hit {
+                line: Line::new(ix as u64 + 1, 0, text.len(), 0),
+                content: text.as_bytes().to_vec(),
+            })
+            .collect()
+    };
+    let texts = |hits: Vec<&Hit>| -> Vec<String> {
+        hits.iter()
+            .map(|hit| String::from_utf8_lossy(&hit.content).to_string())
+            .collect()
+    };
+
+    let old_hits = hits(&["a", "b", "a"]);
+    let new_hits = hits(&["c", "a", "b"]);
+    assert!(same_hits_(&old_hits, &hits(&["a", "b", "a"])));
+    assert!(!same_hits_(&old_hits, &new_hits));
+
+    let (appeared, disappeared) = diff_(&old_hits, &new_hits);
+    assert_eq!(texts(appeared), vec!["c"]);
+    assert_eq!(texts(disappeared), vec!["a"]);
+
+    let (appeared, disappeared) = diff_(&old_hits, &[]);
+    assert!(appeared.is_empty());
+    assert_eq!(disappeared.len(), 3);
+}